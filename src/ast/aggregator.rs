@@ -1,16 +1,11 @@
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub enum AggregationModifierAction {
+    #[default]
     Without,
     By,
 }
 
-impl Default for AggregationModifierAction {
-    fn default() -> Self {
-        Self::Without
-    }
-}
-
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub struct AggregationModifier {
     pub action: AggregationModifierAction,
     pub labels: Vec<String>,
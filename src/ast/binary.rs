@@ -1,7 +1,7 @@
 use super::Expr;
 use crate::ast::op::BinaryOp;
 
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct BinaryExpr {
     pub op: BinaryOp,
     pub lhs: Expr,
@@ -0,0 +1,392 @@
+//! Prints an AST back into PromQL source that `parse_expr` accepts.
+//!
+//! The parser does not keep parentheses, so they are re-inserted from operator
+//! precedence wherever leaving them out would change the tree.
+use crate::ast::{
+    AggregationModifier, AggregationModifierAction, AtModifier, BinaryExpr, BinaryModifier,
+    BinaryModifierAction, BinaryModifierGroupSide, BinaryOp, Expr, FunCall, LabelMatcher,
+    LabelMatcherOp, NumberLiteral, StringLiteral, SubqueryExpr, Vector,
+};
+use std::fmt;
+use std::time::Duration;
+
+/// Formats a duration the way PromQL writes it, e.g. `1h30m` or `500ms`.
+pub fn format_duration(d: Duration) -> String {
+    const UNITS: [(&str, u128); 5] = [
+        ("d", 24 * 60 * 60 * 1000),
+        ("h", 60 * 60 * 1000),
+        ("m", 60 * 1000),
+        ("s", 1000),
+        ("ms", 1),
+    ];
+
+    let mut ms = d.as_millis();
+    if ms == 0 {
+        return "0s".to_owned();
+    }
+
+    let mut out = String::new();
+    for (unit, size) in UNITS.iter() {
+        if ms >= *size {
+            out.push_str(&format!("{}{}", ms / size, unit));
+            ms %= size;
+        }
+    }
+    out
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    // String literals are kept in the escaped source form of whichever quote they
+    // were written with. Double quotes only allow `\"` and `\\`, so escaped
+    // single quotes and backticks are written bare and bare `"` gets escaped.
+    write!(f, "\"")?;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ '\'') | Some(c @ '`') => write!(f, "{}", c)?,
+                Some(c) => write!(f, "\\{}", c)?,
+                None => write!(f, "\\\\")?,
+            },
+            '"' => write!(f, "\\\"")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_labels(f: &mut fmt::Formatter<'_>, labels: &[String]) -> fmt::Result {
+    write!(f, "({})", labels.join(", "))
+}
+
+impl fmt::Display for LabelMatcherOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            LabelMatcherOp::None => "",
+            LabelMatcherOp::Equal => "=",
+            LabelMatcherOp::NotEqual => "!=",
+            LabelMatcherOp::Regexp => "=~",
+            LabelMatcherOp::NotRegexp => "!~",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.name, self.op)?;
+        write_quoted(f, &self.value)
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.label_matchers.is_empty() || self.name.is_empty() {
+            let matchers: Vec<String> = self.label_matchers.iter().map(|m| m.to_string()).collect();
+            write!(f, "{{{}}}", matchers.join(", "))?;
+        }
+        if let Some(range) = self.range {
+            write!(f, "[{}]", format_duration(range))?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " offset {}", format_duration(offset))?;
+        }
+        match self.at {
+            Some(AtModifier::Timestamp(ts)) => write!(f, " @ {}", ts as f64 / 1000.0),
+            Some(AtModifier::Start) => write!(f, " @ start()"),
+            Some(AtModifier::End) => write!(f, " @ end()"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.value.is_nan() {
+            write!(f, "NaN")
+        } else if self.value.is_infinite() {
+            write!(f, "{}Inf", if self.value > 0.0 { "+" } else { "-" })
+        } else {
+            write!(f, "{}", self.value)
+        }
+    }
+}
+
+impl fmt::Display for StringLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quoted(f, &self.value)
+    }
+}
+
+impl fmt::Display for AggregationModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            AggregationModifierAction::By => write!(f, "by ")?,
+            AggregationModifierAction::Without => write!(f, "without ")?,
+        }
+        write_labels(f, &self.labels)
+    }
+}
+
+impl fmt::Display for FunCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(aggregation) = &self.aggregation {
+            write!(f, " {} ", aggregation)?;
+        }
+        let args: Vec<String> = self.args.iter().map(|a| a.to_string()).collect();
+        write!(f, "({})", args.join(", "))
+    }
+}
+
+impl fmt::Display for BinaryModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            BinaryModifierAction::On => write!(f, "on ")?,
+            BinaryModifierAction::Ignore => write!(f, "ignoring ")?,
+        }
+        write_labels(f, &self.labels)?;
+        if let Some(group) = &self.group {
+            match group.side {
+                BinaryModifierGroupSide::Left => write!(f, " group_left ")?,
+                BinaryModifierGroupSide::Right => write!(f, " group_right ")?,
+            }
+            write_labels(f, &group.labels)?;
+        }
+        Ok(())
+    }
+}
+
+impl BinaryOp {
+    /// Binding power of the operator; higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or(_) => 1,
+            BinaryOp::And(_) | BinaryOp::Unless(_) => 2,
+            BinaryOp::Equal(..)
+            | BinaryOp::NotEqual(..)
+            | BinaryOp::GreaterThan(..)
+            | BinaryOp::LessThan(..)
+            | BinaryOp::GreaterEqual(..)
+            | BinaryOp::LessEqual(..) => 3,
+            BinaryOp::Add(_) | BinaryOp::Sub(_) => 4,
+            BinaryOp::Mul(_) | BinaryOp::Div(_) | BinaryOp::Mod(_) => 5,
+            BinaryOp::Power(_) => 6,
+        }
+    }
+
+    /// The operator token, e.g. `+` or `unless`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add(_) => "+",
+            BinaryOp::Sub(_) => "-",
+            BinaryOp::Mul(_) => "*",
+            BinaryOp::Div(_) => "/",
+            BinaryOp::Mod(_) => "%",
+            BinaryOp::Power(_) => "^",
+            BinaryOp::Equal(..) => "==",
+            BinaryOp::NotEqual(..) => "!=",
+            BinaryOp::GreaterThan(..) => ">",
+            BinaryOp::LessThan(..) => "<",
+            BinaryOp::GreaterEqual(..) => ">=",
+            BinaryOp::LessEqual(..) => "<=",
+            BinaryOp::And(_) => "and",
+            BinaryOp::Or(_) => "or",
+            BinaryOp::Unless(_) => "unless",
+        }
+    }
+
+    /// Whether a comparison carries the `bool` modifier.
+    pub fn is_bool(&self) -> bool {
+        match self {
+            BinaryOp::Equal(b, _)
+            | BinaryOp::NotEqual(b, _)
+            | BinaryOp::GreaterThan(b, _)
+            | BinaryOp::LessThan(b, _)
+            | BinaryOp::GreaterEqual(b, _)
+            | BinaryOp::LessEqual(b, _) => *b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())?;
+        if self.is_bool() {
+            write!(f, " bool")?;
+        }
+        if let Some(modifier) = self.modifier() {
+            write!(f, " {}", modifier)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter<'_>, e: &Expr, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({})", e)
+    } else {
+        write!(f, "{}", e)
+    }
+}
+
+impl fmt::Display for BinaryExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precedence = self.op.precedence();
+        let right_assoc = matches!(self.op, BinaryOp::Power(_));
+
+        let needs_parens = |e: &Expr, left: bool| match e {
+            Expr::BinaryExpr(b) => {
+                let p = b.op.precedence();
+                p < precedence || (p == precedence && left == right_assoc)
+            }
+            Expr::SubQueryExpr(_) => true,
+            _ => false,
+        };
+
+        write_operand(f, &self.lhs, needs_parens(&self.lhs, true))?;
+        write!(f, " {} ", self.op)?;
+        write_operand(f, &self.rhs, needs_parens(&self.rhs, false))
+    }
+}
+
+impl fmt::Display for SubqueryExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parenthesize = matches!(
+            self.expr,
+            Expr::BinaryExpr(_) | Expr::SubQueryExpr(_) | Expr::NegationExpr(_)
+        );
+        write_operand(f, &self.expr, parenthesize)?;
+        write!(
+            f,
+            "[{}:{}]",
+            self.range.map(format_duration).unwrap_or_default(),
+            self.resolution.map(format_duration).unwrap_or_default()
+        )
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::BinaryExpr(e) => write!(f, "{}", e),
+            Expr::FunCallExpr(e) => write!(f, "{}", e),
+            Expr::VectorExpr(e) => write!(f, "{}", e),
+            Expr::NumberLiteralExpr(e) => write!(f, "{}", e),
+            Expr::StringLiteralExpr(e) => write!(f, "{}", e),
+            Expr::SubQueryExpr(e) => write!(f, "{}", e),
+            Expr::NegationExpr(e) => {
+                write!(f, "-")?;
+                let parenthesize = matches!(
+                    e.as_ref(),
+                    Expr::BinaryExpr(_)
+                        | Expr::SubQueryExpr(_)
+                        | Expr::NumberLiteralExpr(_)
+                        | Expr::NegationExpr(_)
+                );
+                write_operand(f, e, parenthesize)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn roundtrip(input: &str) -> String {
+        let (rest, expr) = parse_expr(input).unwrap();
+        assert_eq!(rest, "");
+        let printed = expr.to_string();
+        assert_eq!(parse_expr(&printed), Ok(("", expr)), "{}", printed);
+        printed
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(300)), "5m");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s500ms");
+        assert_eq!(format_duration(Duration::from_secs(86400 * 8)), "8d");
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        assert_eq!(
+            roundtrip(
+                r#"sum(rate(http_requests_total{job="api", code=~"5.."}[5m] offset 1h)) by (job)"#
+            ),
+            r#"sum by (job) (rate(http_requests_total{job="api", code=~"5.."}[5m] offset 1h))"#
+        );
+        assert_eq!(roundtrip("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(
+            roundtrip("histogram_quantile(0.9, sum(rate(a[5m])) by (le))"),
+            "histogram_quantile(0.9, sum by (le) (rate(a[5m])))"
+        );
+        assert_eq!(roundtrip("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(roundtrip("(2 ^ 3) ^ 2"), "(2 ^ 3) ^ 2");
+        assert_eq!(roundtrip("2 ^ 3 ^ 2"), "2 ^ 3 ^ 2");
+        assert_eq!(
+            roundtrip("a / ignoring (x) group_left (y) b > bool 0"),
+            "a / ignoring (x) group_left (y) b > bool 0"
+        );
+        assert_eq!(roundtrip("-(a + b)"), "-(a + b)");
+        assert_eq!(roundtrip("-(1) * -2"), "-(1) * -2");
+        assert_eq!(
+            roundtrip("min_over_time(rate(a[5m])[30m:1m])"),
+            "min_over_time(rate(a[5m])[30m:1m])"
+        );
+        assert_eq!(
+            roundtrip(r#"label_replace(up, "a", "$1", "b", "(.*)")"#),
+            r#"label_replace(up, "a", "$1", "b", "(.*)")"#
+        );
+        assert_eq!(roundtrip(r#"{__name__=~"a.*"}"#), r#"{__name__=~"a.*"}"#);
+        assert_eq!(
+            roundtrip(r#"{a="say \"hi\"", b="\\"}"#),
+            r#"{a="say \"hi\"", b="\\"}"#
+        );
+        assert_eq!(
+            roundtrip("rate(a[5m] @ 1609746000.5 offset 1m) + b @ end()"),
+            "rate(a[5m] offset 1m @ 1609746000.5) + b @ end()"
+        );
+    }
+
+    #[test]
+    fn test_display_converts_quotes() {
+        let input = r#"label_replace(a{b='it\'s', c=`"x"`}, "d", 'e\\f', "g", `h\`i`)"#;
+        let expr = parse_expr(input).unwrap().1;
+        let printed = expr.to_string();
+        assert_eq!(
+            printed,
+            r#"label_replace(a{b="it's", c="\"x\""}, "d", "e\\f", "g", "h`i")"#
+        );
+
+        let reparsed = parse_expr(&printed).unwrap().1;
+        assert_eq!(reparsed.to_string(), printed);
+        let values = |e: &Expr| -> Vec<String> {
+            match e {
+                Expr::FunCallExpr(f) => {
+                    let mut out: Vec<String> = match &f.args[0] {
+                        Expr::VectorExpr(v) => v
+                            .label_matchers
+                            .iter()
+                            .map(|m| crate::ast::unescape(&m.value))
+                            .collect(),
+                        _ => unreachable!(),
+                    };
+                    out.extend(f.args[1..].iter().map(|a| match a {
+                        Expr::StringLiteralExpr(s) => s.unescaped(),
+                        _ => unreachable!(),
+                    }));
+                    out
+                }
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(values(&reparsed), values(&expr));
+    }
+}
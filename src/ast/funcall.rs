@@ -1,13 +1,38 @@
 use crate::ast::aggregator::AggregationModifier;
 use crate::ast::Expr;
 
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub struct FunCall {
     pub name: String,
     pub args: Vec<Expr>,
     pub aggregation: Option<AggregationModifier>,
 }
 
+/// Names of the aggregation operators, which the parser reads as function calls.
+pub const AGGREGATION_OPERATORS: &[&str] = &[
+    "sum",
+    "avg",
+    "count",
+    "min",
+    "max",
+    "group",
+    "stddev",
+    "stdvar",
+    "topk",
+    "bottomk",
+    "quantile",
+    "count_values",
+    "limitk",
+    "limit_ratio",
+];
+
+impl FunCall {
+    /// Whether the call is an aggregation operator such as `sum` rather than a function.
+    pub fn is_aggregation(&self) -> bool {
+        AGGREGATION_OPERATORS.contains(&self.name.as_str())
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) fn fun_call(name: &str, args: Vec<Expr>) -> FunCall {
     FunCall {
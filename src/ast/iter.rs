@@ -0,0 +1,246 @@
+use crate::ast::{Expr, FunCall, Vector};
+use std::fmt;
+
+/// One step from a node to one of its children.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PathSegment {
+    /// Left-hand side of a binary expression.
+    Lhs,
+    /// Right-hand side of a binary expression.
+    Rhs,
+    /// Argument of a function call or aggregation.
+    Arg(usize),
+    /// Inner expression of a subquery or a negation.
+    Inner,
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Lhs => write!(f, ".lhs"),
+            PathSegment::Rhs => write!(f, ".rhs"),
+            PathSegment::Arg(i) => write!(f, ".args[{}]", i),
+            PathSegment::Inner => write!(f, ".expr"),
+        }
+    }
+}
+
+/// Location of a node relative to the root `Expr`, e.g. `$.lhs.args[0]`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Path(pub Vec<PathSegment>);
+
+impl Path {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn child(&self, segment: PathSegment) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(segment);
+        Self(segments)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "$")?;
+        self.0.iter().try_for_each(|s| write!(f, "{}", s))
+    }
+}
+
+impl Expr {
+    /// Direct children in evaluation order, tagged with the step leading to them.
+    pub fn children(&self) -> Children<'_> {
+        let children = match self {
+            Expr::BinaryExpr(e) => vec![(PathSegment::Lhs, &e.lhs), (PathSegment::Rhs, &e.rhs)],
+            Expr::FunCallExpr(e) => e
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| (PathSegment::Arg(i), arg))
+                .collect(),
+            Expr::SubQueryExpr(e) => vec![(PathSegment::Inner, &e.expr)],
+            Expr::NegationExpr(e) => vec![(PathSegment::Inner, e.as_ref())],
+            Expr::VectorExpr(_) | Expr::NumberLiteralExpr(_) | Expr::StringLiteralExpr(_) => {
+                vec![]
+            }
+        };
+
+        Children {
+            inner: children.into_iter(),
+        }
+    }
+
+    /// All nodes below `self` in depth-first pre-order, excluding `self`.
+    pub fn descendants(&self) -> Descendants<'_> {
+        let mut it = Descendants::new(self);
+        it.next();
+        it
+    }
+
+    /// `self` followed by all of its descendants in depth-first pre-order.
+    pub fn walk(&self) -> Descendants<'_> {
+        Descendants::new(self)
+    }
+
+    /// All vector selectors, instant and range alike.
+    pub fn selectors(&self) -> impl Iterator<Item = (Path, &Vector)> {
+        self.walk().filter_map(|(path, e)| match e {
+            Expr::VectorExpr(v) => Some((path, v.as_ref())),
+            _ => None,
+        })
+    }
+
+    /// Vector selectors with a range, e.g. `http_requests_total[5m]`.
+    pub fn matrix_selectors(&self) -> impl Iterator<Item = (Path, &Vector)> {
+        self.selectors().filter(|(_, v)| v.range.is_some())
+    }
+
+    /// All function calls, including aggregations such as `sum`.
+    pub fn functions(&self) -> impl Iterator<Item = (Path, &FunCall)> {
+        self.walk().filter_map(|(path, e)| match e {
+            Expr::FunCallExpr(f) => Some((path, f.as_ref())),
+            _ => None,
+        })
+    }
+
+    /// Labels listed in the `by (…)`/`without (…)` clauses of aggregations.
+    pub fn grouping_labels(&self) -> impl Iterator<Item = (Path, &str)> {
+        self.functions().flat_map(|(path, f)| {
+            f.aggregation
+                .iter()
+                .flat_map(|a| a.labels.iter())
+                .map(move |label| (path.clone(), label.as_str()))
+        })
+    }
+
+    /// Looks up the node at `path`, if it exists.
+    pub fn get(&self, path: &Path) -> Option<&Expr> {
+        path.segments().iter().try_fold(self, |e, segment| {
+            e.children()
+                .find(|(s, _)| s == segment)
+                .map(|(_, child)| child)
+        })
+    }
+}
+
+pub struct Children<'a> {
+    inner: std::vec::IntoIter<(PathSegment, &'a Expr)>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = (PathSegment, &'a Expr);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+pub struct Descendants<'a> {
+    stack: Vec<(Path, &'a Expr)>,
+}
+
+impl<'a> Descendants<'a> {
+    fn new(root: &'a Expr) -> Self {
+        Self {
+            stack: vec![(Path::root(), root)],
+        }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = (Path, &'a Expr);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, e) = self.stack.pop()?;
+        let children: Vec<_> = e.children().collect();
+        self.stack.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|(segment, child)| (path.child(segment), child)),
+        );
+        Some((path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    #[test]
+    fn test_selectors() {
+        let (_, expr) =
+            parse_expr("sum(rate(a{job=\"x\"}[5m])) by (job, instance) / b + c[1m]").unwrap();
+
+        let selectors: Vec<(String, &str)> = expr
+            .selectors()
+            .map(|(p, v)| (p.to_string(), v.name.as_str()))
+            .collect();
+        assert_eq!(
+            selectors,
+            vec![
+                ("$.lhs.lhs.args[0].args[0]".to_owned(), "a"),
+                ("$.lhs.rhs".to_owned(), "b"),
+                ("$.rhs".to_owned(), "c"),
+            ]
+        );
+
+        let matrix: Vec<&str> = expr
+            .matrix_selectors()
+            .map(|(_, v)| v.name.as_str())
+            .collect();
+        assert_eq!(matrix, vec!["a", "c"]);
+
+        for (path, v) in expr.selectors() {
+            assert_eq!(
+                expr.get(&path),
+                Some(&Expr::VectorExpr(Box::new(v.clone())))
+            );
+        }
+    }
+
+    #[test]
+    fn test_functions_and_grouping_labels() {
+        let (_, expr) =
+            parse_expr("max_over_time(sum(rate(a[1m])) by (job)[10m:1m]) > -avg(b) without (x)")
+                .unwrap();
+
+        let functions: Vec<&str> = expr.functions().map(|(_, f)| f.name.as_str()).collect();
+        assert_eq!(functions, vec!["max_over_time", "sum", "rate", "avg"]);
+
+        let labels: Vec<(String, &str)> = expr
+            .grouping_labels()
+            .map(|(p, l)| (p.to_string(), l))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("$.lhs.args[0].expr".to_owned(), "job"),
+                ("$.rhs.expr".to_owned(), "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_children_and_descendants() {
+        let (_, expr) = parse_expr("a + f(b, 1)").unwrap();
+        assert_eq!(expr.children().count(), 2);
+        assert_eq!(expr.descendants().count(), 4);
+        assert_eq!(expr.walk().count(), 5);
+        assert_eq!(expr.get(&Path::root()), Some(&expr));
+        assert_eq!(
+            expr.get(&Path(vec![PathSegment::Rhs, PathSegment::Arg(2)])),
+            None
+        );
+    }
+}
@@ -1,23 +1,54 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
 #[derive(Debug, Clone)]
 pub struct NumberLiteral {
     pub value: f64,
 }
 
+/// Literals compare by bit pattern, so that equality, ordering and hashing agree.
+/// All NaNs are equal to each other, while `0` and `-0` are not.
 impl PartialEq for NumberLiteral {
     fn eq(&self, other: &Self) -> bool {
-        (self.value - other.value).abs() <= f64::EPSILON
+        self.canonical_bits() == other.canonical_bits()
     }
 }
 
 impl Eq for NumberLiteral {}
 
+impl Hash for NumberLiteral {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_bits().hash(state);
+    }
+}
+
+impl PartialOrd for NumberLiteral {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NumberLiteral {
+    fn cmp(&self, other: &Self) -> Ordering {
+        f64::from_bits(self.canonical_bits()).total_cmp(&f64::from_bits(other.canonical_bits()))
+    }
+}
+
 impl NumberLiteral {
     pub fn new(value: f64) -> Self {
         Self { value }
     }
+
+    fn canonical_bits(&self) -> u64 {
+        if self.value.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            self.value.to_bits()
+        }
+    }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct StringLiteral {
     pub value: String,
 }
@@ -26,6 +57,32 @@ impl StringLiteral {
     pub fn new(value: String) -> Self {
         Self { value }
     }
+
+    /// The string with its escape sequences resolved.
+    pub fn unescaped(&self) -> String {
+        unescape(&self.value)
+    }
+}
+
+/// Resolves the escape sequences of a string in its source form, as kept in
+/// `StringLiteral` and `LabelMatcher` values.
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
 }
 
 #[allow(dead_code)]
@@ -2,9 +2,12 @@ use std::time::Duration;
 
 pub use aggregator::*;
 pub use binary::*;
+pub use display::*;
 pub use funcall::*;
+pub use iter::*;
 pub use literal::*;
 pub use modifier::*;
+pub use normalize::*;
 pub use op::*;
 pub use selector::*;
 pub use subquery::*;
@@ -13,21 +16,24 @@ pub use vector::*;
 
 pub mod aggregator;
 pub mod binary;
+pub mod display;
 pub mod funcall;
+pub mod iter;
 pub mod literal;
 pub mod modifier;
+pub mod normalize;
 pub mod op;
 pub mod selector;
 pub mod subquery;
 pub mod value;
 pub mod vector;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum Node {
     None,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum Expr {
     BinaryExpr(Box<BinaryExpr>),
     FunCallExpr(Box<FunCall>),
@@ -1,5 +1,5 @@
 /// Vector matching operator modifier (`on (…)`/`ignoring (…)`).
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub struct BinaryModifier {
     /// Action applied to a list of vectors; whether `on (…)` or `ignored(…)` is used after the operator.
     pub action: BinaryModifierAction,
@@ -43,32 +43,22 @@ pub fn binary_modifier_group_left(labels: Vec<String>) -> BinaryModifierGroup {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub enum BinaryModifierAction {
+    #[default]
     On,
     Ignore,
 }
 
-impl Default for BinaryModifierAction {
-    fn default() -> Self {
-        Self::On
-    }
-}
-
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub enum BinaryModifierGroupSide {
+    #[default]
     Left,
     Right,
 }
 
-impl Default for BinaryModifierGroupSide {
-    fn default() -> Self {
-        Self::Left
-    }
-}
-
 /// Vector grouping operator modifier (`group_left(…)`/`group_right(…)`).
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub struct BinaryModifierGroup {
     pub side: BinaryModifierGroupSide,
     pub labels: Vec<String>,
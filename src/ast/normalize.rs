@@ -0,0 +1,163 @@
+use crate::ast::{BinaryExpr, Expr, FunCall, LabelMatcher, LabelMatcherOp, Vector};
+use crate::visitor::{walk_mut, VisitorMut};
+use std::convert::Infallible;
+use std::hash::Hasher;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash. Unlike `std`'s hashers its output is stable across
+/// platforms and releases, so it can be persisted.
+pub fn fnv1a_64(data: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// Streaming form of `fnv1a_64`.
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, b| {
+            (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME)
+        });
+    }
+}
+
+fn is_metric_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+struct Normalizer;
+
+impl VisitorMut for Normalizer {
+    type Err = Infallible;
+
+    fn visit_binary_expr_mut(&mut self, ast: &mut BinaryExpr) -> Result<(), Self::Err> {
+        if let Some(m) = ast.op.modifier_mut() {
+            m.labels.sort();
+            m.labels.dedup();
+            if let Some(g) = m.group.as_mut() {
+                g.labels.sort();
+                g.labels.dedup();
+            }
+        }
+        self.visit_expr_mut(&mut ast.lhs)?;
+        self.visit_expr_mut(&mut ast.rhs)
+    }
+
+    fn visit_funcall_expr_mut(&mut self, ast: &mut FunCall) -> Result<(), Self::Err> {
+        if let Some(a) = ast.aggregation.as_mut() {
+            a.labels.sort();
+            a.labels.dedup();
+        }
+        ast.args
+            .iter_mut()
+            .try_for_each(|arg| self.visit_expr_mut(arg))
+    }
+
+    fn visit_vector_expr_mut(&mut self, ast: &mut Vector) -> Result<(), Self::Err> {
+        let is_name = |m: &LabelMatcher| m.name == "__name__" && m.op == LabelMatcherOp::Equal;
+
+        if ast.name.is_empty() {
+            if let Some(i) = ast
+                .label_matchers
+                .iter()
+                .position(|m| is_name(m) && is_metric_name(&m.value))
+            {
+                ast.name = ast.label_matchers.remove(i).value;
+            }
+        }
+        let name = ast.name.clone();
+        ast.label_matchers
+            .retain(|m| !(is_name(m) && m.value == name));
+
+        ast.label_matchers
+            .sort_by(|a, b| (&a.name, &a.op, &a.value).cmp(&(&b.name, &b.op, &b.value)));
+        ast.label_matchers.dedup();
+        Ok(())
+    }
+}
+
+impl Expr {
+    /// Canonical form of the expression: label matchers and grouping labels are
+    /// sorted and deduplicated, and `{__name__="x"}` becomes `x`. Expressions that
+    /// only differ in how they were written normalise to equal trees.
+    pub fn normalize(&self) -> Expr {
+        let mut expr = self.clone();
+        let _ = walk_mut(&mut expr, &mut Normalizer);
+        expr
+    }
+
+    /// Stable hash of the normalised expression, suitable for de-duplication and
+    /// as a cache key.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a_64(self.normalize().to_string().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::NumberLiteral;
+    use crate::parse_expr;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_normalize() {
+        let (_, a) =
+            parse_expr(r#"sum by (z, y) ({a="1", __name__="x", b="2"}) / ignoring (b, a) y"#)
+                .unwrap();
+        let (_, b) =
+            parse_expr(r#"sum(x{b="2", a="1"}) by (y, z) / ignoring (a, b) {__name__="y"}"#)
+                .unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(a.normalize(), b.normalize());
+        assert_eq!(
+            a.normalize().to_string(),
+            r#"sum by (y, z) (x{a="1", b="2"}) / ignoring (a, b) y"#
+        );
+        assert_eq!(a.fingerprint(), b.fingerprint());
+
+        let (_, c) = parse_expr(r#"{__name__=~"x|y"}"#).unwrap();
+        assert_eq!(c.normalize(), c);
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        let (_, expr) = parse_expr("rate(a[5m])").unwrap();
+        assert_eq!(expr.fingerprint(), super::fnv1a_64(b"rate(a[5m])"));
+        assert_eq!(super::fnv1a_64(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::fnv1a_64(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_number_literal_equivalence() {
+        assert_eq!(NumberLiteral::new(f64::NAN), NumberLiteral::new(-f64::NAN));
+        assert_ne!(NumberLiteral::new(0.0), NumberLiteral::new(-0.0));
+        assert_ne!(
+            NumberLiteral::new(1.0),
+            NumberLiteral::new(1.0 + f64::EPSILON)
+        );
+        assert!(NumberLiteral::new(-0.0) < NumberLiteral::new(0.0));
+
+        let (_, a) = parse_expr("x > NaN").unwrap();
+        let (_, b) = parse_expr("x > NaN").unwrap();
+        let set: HashSet<_> = vec![a, b].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::ast::modifier::BinaryModifier;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum BinaryOp {
     Add(Option<BinaryModifier>),
     Sub(Option<BinaryModifier>),
@@ -26,3 +26,46 @@ impl Default for BinaryOp {
         Self::Add(None)
     }
 }
+
+impl BinaryOp {
+    /// The `on (…)`/`ignoring (…)` modifier attached to the operator, if any.
+    pub fn modifier(&self) -> Option<&BinaryModifier> {
+        match self {
+            BinaryOp::Add(m)
+            | BinaryOp::Sub(m)
+            | BinaryOp::Mul(m)
+            | BinaryOp::Div(m)
+            | BinaryOp::Mod(m)
+            | BinaryOp::Power(m)
+            | BinaryOp::Equal(_, m)
+            | BinaryOp::NotEqual(_, m)
+            | BinaryOp::GreaterThan(_, m)
+            | BinaryOp::LessThan(_, m)
+            | BinaryOp::GreaterEqual(_, m)
+            | BinaryOp::LessEqual(_, m)
+            | BinaryOp::And(m)
+            | BinaryOp::Or(m)
+            | BinaryOp::Unless(m) => m.as_ref(),
+        }
+    }
+
+    pub fn modifier_mut(&mut self) -> &mut Option<BinaryModifier> {
+        match self {
+            BinaryOp::Add(m)
+            | BinaryOp::Sub(m)
+            | BinaryOp::Mul(m)
+            | BinaryOp::Div(m)
+            | BinaryOp::Mod(m)
+            | BinaryOp::Power(m)
+            | BinaryOp::Equal(_, m)
+            | BinaryOp::NotEqual(_, m)
+            | BinaryOp::GreaterThan(_, m)
+            | BinaryOp::LessThan(_, m)
+            | BinaryOp::GreaterEqual(_, m)
+            | BinaryOp::LessEqual(_, m)
+            | BinaryOp::And(m)
+            | BinaryOp::Or(m)
+            | BinaryOp::Unless(m) => m,
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use std::time::Duration;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub enum LabelMatcherOp {
+    #[default]
     None,
     Equal,
     NotEqual,
@@ -9,13 +10,7 @@ pub enum LabelMatcherOp {
     NotRegexp,
 }
 
-impl Default for LabelMatcherOp {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct LabelMatcher {
     pub op: LabelMatcherOp,
     pub name: String,
@@ -24,15 +19,24 @@ pub struct LabelMatcher {
     // fast regex matcher
 }
 
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+/// `@` modifier pinning a selector to a fixed evaluation time.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum AtModifier {
+    /// `@ <timestamp>`, in milliseconds since the Unix epoch.
+    Timestamp(i64),
+    /// `@ start()`, the start of the range query.
+    Start,
+    /// `@ end()`, the end of the range query.
+    End,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct Vector {
     pub name: String,
-    // pub original_offset: Duration,
-    // pub timestamp: u64,
-    // pub start_or_end:    // Set when @ is used with start() or end()
     pub label_matchers: Vec<LabelMatcher>,
     pub offset: Option<Duration>,
     pub range: Option<Duration>,
+    pub at: Option<AtModifier>,
 }
 
 pub fn v(name: &str) -> Vector {
@@ -1,7 +1,7 @@
 use crate::ast::Expr;
 use std::time::Duration;
 
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct SubqueryExpr {
     pub expr: Expr,
     pub range: Option<Duration>,
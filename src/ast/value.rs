@@ -1,14 +1,9 @@
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, PartialOrd, Ord)]
 pub enum ValueType {
+    #[default]
     None,
     Vector,
     Scalar,
     Matrix,
     String,
 }
-
-impl Default for ValueType {
-    fn default() -> Self {
-        Self::None
-    }
-}
@@ -19,6 +19,7 @@ pub fn vector_labels_range(
         label_matchers,
         range,
         offset: None,
+        at: None,
     }
 }
 
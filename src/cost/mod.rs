@@ -0,0 +1,169 @@
+use crate::ast::{Expr, Vector};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostOptions {
+    /// Resolution assumed for subqueries written without one (`[1h:]`), which
+    /// Prometheus evaluates at the global evaluation interval.
+    pub default_resolution: Duration,
+}
+
+impl Default for CostOptions {
+    fn default() -> Self {
+        Self {
+            default_resolution: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Static cost estimate of a query, computed from the AST alone.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct QueryCost {
+    /// Number of vector selectors, instant and range alike.
+    pub selectors: usize,
+    /// Number of selectors with a range, e.g. `a[5m]`.
+    pub matrix_selectors: usize,
+    /// Longest distance back from evaluation time that any selector reads:
+    /// its range and offset plus the ranges of all enclosing subqueries.
+    pub max_range: Duration,
+    /// Sum of the distance covered by every selector.
+    pub total_range: Duration,
+    /// Inner evaluations caused by subqueries, multiplied through nesting, so
+    /// `max_over_time(rate(a[5m])[1h:1m])[1d:1h]` counts `24 + 24 * 60` steps.
+    pub subquery_steps: u64,
+    /// Estimated series loaded, with each selector weighted by how often it is
+    /// evaluated. Only set when a series estimator is supplied.
+    pub series: Option<u64>,
+}
+
+impl QueryCost {
+    pub fn from_expr(expr: &Expr) -> Self {
+        Self::with_options(expr, &CostOptions::default())
+    }
+
+    pub fn with_options(expr: &Expr, opts: &CostOptions) -> Self {
+        let mut cost = Self::default();
+        cost.collect(
+            expr,
+            opts,
+            &mut None::<fn(&Vector) -> u64>,
+            1,
+            Duration::default(),
+        );
+        cost
+    }
+
+    /// Like `with_options`, additionally asking `estimate` for the number of series
+    /// each selector matches.
+    pub fn with_series_estimator<F>(expr: &Expr, opts: &CostOptions, estimate: F) -> Self
+    where
+        F: FnMut(&Vector) -> u64,
+    {
+        let mut cost = Self {
+            series: Some(0),
+            ..Default::default()
+        };
+        cost.collect(expr, opts, &mut Some(estimate), 1, Duration::default());
+        cost
+    }
+
+    fn collect<F>(
+        &mut self,
+        expr: &Expr,
+        opts: &CostOptions,
+        estimate: &mut Option<F>,
+        evaluations: u64,
+        enclosing_range: Duration,
+    ) where
+        F: FnMut(&Vector) -> u64,
+    {
+        match expr {
+            Expr::VectorExpr(v) => {
+                let covered =
+                    enclosing_range + v.range.unwrap_or_default() + v.offset.unwrap_or_default();
+
+                self.selectors += 1;
+                if v.range.is_some() {
+                    self.matrix_selectors += 1;
+                }
+                self.max_range = self.max_range.max(covered);
+                self.total_range += covered;
+                if let (Some(series), Some(f)) = (self.series.as_mut(), estimate.as_mut()) {
+                    *series = series.saturating_add(f(v).saturating_mul(evaluations));
+                }
+            }
+            Expr::SubQueryExpr(e) => {
+                let range = e.range.unwrap_or_default();
+                let steps = subquery_steps(range, e.resolution.unwrap_or(opts.default_resolution));
+                let evaluations = evaluations.saturating_mul(steps);
+
+                self.subquery_steps = self.subquery_steps.saturating_add(evaluations);
+                self.collect(
+                    &e.expr,
+                    opts,
+                    estimate,
+                    evaluations,
+                    enclosing_range + range,
+                );
+            }
+            _ => expr.children().for_each(|(_, child)| {
+                self.collect(child, opts, estimate, evaluations, enclosing_range)
+            }),
+        }
+    }
+}
+
+fn subquery_steps(range: Duration, resolution: Duration) -> u64 {
+    let resolution = resolution.as_millis().max(1);
+    let steps = range.as_millis().div_ceil(resolution);
+    steps.max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    #[test]
+    fn test_selector_cost() {
+        let (_, expr) = parse_expr("sum(rate(a[5m] offset 1h)) / b").unwrap();
+        assert_eq!(
+            QueryCost::from_expr(&expr),
+            QueryCost {
+                selectors: 2,
+                matrix_selectors: 1,
+                max_range: Duration::from_secs(65 * 60),
+                total_range: Duration::from_secs(65 * 60),
+                subquery_steps: 0,
+                series: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_nested_subquery_cost() {
+        let (_, expr) =
+            parse_expr("max_over_time(max_over_time(rate(a[5m])[1h:1m])[1d:1h])").unwrap();
+        let cost = QueryCost::with_series_estimator(&expr, &CostOptions::default(), |_| 10);
+
+        assert_eq!(cost.subquery_steps, 24 + 24 * 60);
+        assert_eq!(
+            cost.max_range,
+            Duration::from_secs(24 * 3600 + 3600 + 5 * 60)
+        );
+        assert_eq!(cost.series, Some(10 * 24 * 60));
+    }
+
+    #[test]
+    fn test_series_estimate() {
+        let (_, expr) = parse_expr("a + on (x) b{job=\"api\"}").unwrap();
+        let cost = QueryCost::with_series_estimator(&expr, &CostOptions::default(), |v| {
+            if v.label_matchers.is_empty() {
+                100
+            } else {
+                3
+            }
+        });
+        assert_eq!(cost.series, Some(103));
+    }
+}
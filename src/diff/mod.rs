@@ -0,0 +1,515 @@
+//! Semantic differences between two expressions.
+//!
+//! Both sides are normalised first, so reordering matchers or grouping labels is
+//! not a change. The AST does not keep source spans, so every edit is located by
+//! the `Path` of the affected node in the old and in the new expression.
+use crate::ast::{
+    format_duration, AggregationModifier, AtModifier, BinaryOp, Expr, LabelMatcher, Path,
+    PathSegment, Vector,
+};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    MetricChanged {
+        from: String,
+        to: String,
+    },
+    MatcherAdded(LabelMatcher),
+    MatcherRemoved(LabelMatcher),
+    MatcherChanged {
+        from: LabelMatcher,
+        to: LabelMatcher,
+    },
+    RangeChanged {
+        from: Option<Duration>,
+        to: Option<Duration>,
+    },
+    ResolutionChanged {
+        from: Option<Duration>,
+        to: Option<Duration>,
+    },
+    OffsetChanged {
+        from: Option<Duration>,
+        to: Option<Duration>,
+    },
+    AtChanged {
+        from: Option<AtModifier>,
+        to: Option<AtModifier>,
+    },
+    FunctionChanged {
+        from: String,
+        to: String,
+    },
+    GroupingChanged {
+        from: Option<AggregationModifier>,
+        to: Option<AggregationModifier>,
+    },
+    GroupingLabelAdded(String),
+    GroupingLabelRemoved(String),
+    OperatorChanged {
+        from: BinaryOp,
+        to: BinaryOp,
+    },
+    ArgumentAdded(Expr),
+    ArgumentRemoved(Expr),
+    NumberChanged {
+        from: f64,
+        to: f64,
+    },
+    StringChanged {
+        from: String,
+        to: String,
+    },
+    /// The old node is now nested inside a new one, e.g. `x` became `sum(x)`.
+    Wrapped(Expr),
+    /// The old node was nested inside one that has been removed.
+    Unwrapped(Expr),
+    Replaced {
+        from: Expr,
+        to: Expr,
+    },
+}
+
+/// One change, located in both expressions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub old_path: Path,
+    pub new_path: Path,
+    pub change: Change,
+}
+
+/// All edits turning one expression into another, in pre-order of the old one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    pub edits: Vec<Edit>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+pub fn diff(old: &Expr, new: &Expr) -> Diff {
+    let mut edits = Vec::new();
+    diff_node(
+        &old.normalize(),
+        &new.normalize(),
+        Path::root(),
+        Path::root(),
+        &mut edits,
+    );
+    Diff { edits }
+}
+
+struct Differ<'a> {
+    old_path: &'a Path,
+    new_path: &'a Path,
+    edits: &'a mut Vec<Edit>,
+}
+
+impl Differ<'_> {
+    fn push(&mut self, change: Change) {
+        self.edits.push(Edit {
+            old_path: self.old_path.clone(),
+            new_path: self.new_path.clone(),
+            change,
+        });
+    }
+}
+
+/// Short description of a node for wrap and unwrap edits: the node with its
+/// children elided.
+fn outline(e: &Expr) -> String {
+    match e {
+        Expr::FunCallExpr(f) => match &f.aggregation {
+            Some(a) => format!("{} {} (…)", f.name, a),
+            None => format!("{}(…)", f.name),
+        },
+        Expr::BinaryExpr(b) => format!("… {} …", b.op),
+        Expr::SubQueryExpr(s) => format!(
+            "(…)[{}:{}]",
+            s.range.map(format_duration).unwrap_or_default(),
+            s.resolution.map(format_duration).unwrap_or_default()
+        ),
+        Expr::NegationExpr(_) => "-(…)".to_owned(),
+        _ => e.to_string(),
+    }
+}
+
+fn wrapping_child(outer: &Expr, inner: &Expr) -> Option<PathSegment> {
+    outer
+        .children()
+        .find(|(_, child)| *child == inner)
+        .map(|(seg, _)| seg)
+}
+
+fn diff_node(old: &Expr, new: &Expr, old_path: Path, new_path: Path, edits: &mut Vec<Edit>) {
+    if old == new {
+        return;
+    }
+
+    if let Some(seg) = wrapping_child(new, old) {
+        edits.push(Edit {
+            old_path,
+            new_path: new_path.child(seg),
+            change: Change::Wrapped(new.clone()),
+        });
+        return;
+    }
+    if let Some(seg) = wrapping_child(old, new) {
+        edits.push(Edit {
+            old_path: old_path.child(seg),
+            new_path,
+            change: Change::Unwrapped(old.clone()),
+        });
+        return;
+    }
+
+    let mut d = Differ {
+        old_path: &old_path,
+        new_path: &new_path,
+        edits,
+    };
+    match (old, new) {
+        (Expr::VectorExpr(a), Expr::VectorExpr(b)) => diff_vector(a, b, &mut d),
+        (Expr::FunCallExpr(a), Expr::FunCallExpr(b)) => {
+            if a.name != b.name {
+                d.push(Change::FunctionChanged {
+                    from: a.name.clone(),
+                    to: b.name.clone(),
+                });
+            }
+            diff_grouping(&a.aggregation, &b.aggregation, &mut d);
+
+            for (i, (x, y)) in a.args.iter().zip(&b.args).enumerate() {
+                let seg = PathSegment::Arg(i);
+                diff_node(x, y, old_path.child(seg), new_path.child(seg), edits);
+            }
+            for (i, arg) in a.args.iter().enumerate().skip(b.args.len()) {
+                edits.push(Edit {
+                    old_path: old_path.child(PathSegment::Arg(i)),
+                    new_path: new_path.clone(),
+                    change: Change::ArgumentRemoved(arg.clone()),
+                });
+            }
+            for (i, arg) in b.args.iter().enumerate().skip(a.args.len()) {
+                edits.push(Edit {
+                    old_path: old_path.clone(),
+                    new_path: new_path.child(PathSegment::Arg(i)),
+                    change: Change::ArgumentAdded(arg.clone()),
+                });
+            }
+        }
+        (Expr::BinaryExpr(a), Expr::BinaryExpr(b)) => {
+            if a.op != b.op {
+                d.push(Change::OperatorChanged {
+                    from: a.op.clone(),
+                    to: b.op.clone(),
+                });
+            }
+            for (seg, x, y) in [
+                (PathSegment::Lhs, &a.lhs, &b.lhs),
+                (PathSegment::Rhs, &a.rhs, &b.rhs),
+            ] {
+                diff_node(x, y, old_path.child(seg), new_path.child(seg), edits);
+            }
+        }
+        (Expr::SubQueryExpr(a), Expr::SubQueryExpr(b)) => {
+            if a.range != b.range {
+                d.push(Change::RangeChanged {
+                    from: a.range,
+                    to: b.range,
+                });
+            }
+            if a.resolution != b.resolution {
+                d.push(Change::ResolutionChanged {
+                    from: a.resolution,
+                    to: b.resolution,
+                });
+            }
+            let seg = PathSegment::Inner;
+            diff_node(
+                &a.expr,
+                &b.expr,
+                old_path.child(seg),
+                new_path.child(seg),
+                edits,
+            );
+        }
+        (Expr::NegationExpr(a), Expr::NegationExpr(b)) => {
+            let seg = PathSegment::Inner;
+            diff_node(a, b, old_path.child(seg), new_path.child(seg), edits);
+        }
+        (Expr::NumberLiteralExpr(a), Expr::NumberLiteralExpr(b)) => d.push(Change::NumberChanged {
+            from: a.value,
+            to: b.value,
+        }),
+        (Expr::StringLiteralExpr(a), Expr::StringLiteralExpr(b)) => d.push(Change::StringChanged {
+            from: a.value.clone(),
+            to: b.value.clone(),
+        }),
+        _ => d.push(Change::Replaced {
+            from: old.clone(),
+            to: new.clone(),
+        }),
+    }
+}
+
+fn diff_vector(a: &Vector, b: &Vector, d: &mut Differ<'_>) {
+    if a.name != b.name {
+        d.push(Change::MetricChanged {
+            from: a.name.clone(),
+            to: b.name.clone(),
+        });
+    }
+
+    let names: BTreeSet<&String> = a
+        .label_matchers
+        .iter()
+        .chain(&b.label_matchers)
+        .map(|m| &m.name)
+        .collect();
+    for name in names {
+        let old: Vec<&LabelMatcher> = a
+            .label_matchers
+            .iter()
+            .filter(|m| &m.name == name)
+            .collect();
+        let new: Vec<&LabelMatcher> = b
+            .label_matchers
+            .iter()
+            .filter(|m| &m.name == name)
+            .collect();
+        match (old.as_slice(), new.as_slice()) {
+            ([x], [y]) if x != y => d.push(Change::MatcherChanged {
+                from: (*x).clone(),
+                to: (*y).clone(),
+            }),
+            _ => {
+                for m in old.iter().filter(|m| !new.contains(m)) {
+                    d.push(Change::MatcherRemoved((*m).clone()));
+                }
+                for m in new.iter().filter(|m| !old.contains(m)) {
+                    d.push(Change::MatcherAdded((*m).clone()));
+                }
+            }
+        }
+    }
+
+    if a.range != b.range {
+        d.push(Change::RangeChanged {
+            from: a.range,
+            to: b.range,
+        });
+    }
+    if a.offset != b.offset {
+        d.push(Change::OffsetChanged {
+            from: a.offset,
+            to: b.offset,
+        });
+    }
+    if a.at != b.at {
+        d.push(Change::AtChanged {
+            from: a.at.clone(),
+            to: b.at.clone(),
+        });
+    }
+}
+
+fn diff_grouping(
+    a: &Option<AggregationModifier>,
+    b: &Option<AggregationModifier>,
+    d: &mut Differ<'_>,
+) {
+    match (a, b) {
+        (Some(x), Some(y)) if x.action == y.action => {
+            for l in x.labels.iter().filter(|l| !y.labels.contains(l)) {
+                d.push(Change::GroupingLabelRemoved(l.clone()));
+            }
+            for l in y.labels.iter().filter(|l| !x.labels.contains(l)) {
+                d.push(Change::GroupingLabelAdded(l.clone()));
+            }
+        }
+        _ if a != b => d.push(Change::GroupingChanged {
+            from: a.clone(),
+            to: b.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn fmt_duration(d: &Option<Duration>) -> String {
+    d.map(format_duration).unwrap_or_else(|| "none".to_owned())
+}
+
+fn fmt_at(at: &Option<AtModifier>) -> String {
+    match at {
+        Some(AtModifier::Timestamp(ts)) => format!("@ {}", *ts as f64 / 1000.0),
+        Some(AtModifier::Start) => "@ start()".to_owned(),
+        Some(AtModifier::End) => "@ end()".to_owned(),
+        None => "none".to_owned(),
+    }
+}
+
+fn fmt_grouping(a: &Option<AggregationModifier>) -> String {
+    match a {
+        Some(a) => a.to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::MetricChanged { from, to } => {
+                write!(f, "metric changed from {} to {}", from, to)
+            }
+            Change::MatcherAdded(m) => write!(f, "matcher {} added", m),
+            Change::MatcherRemoved(m) => write!(f, "matcher {} removed", m),
+            Change::MatcherChanged { from, to } => {
+                write!(f, "matcher changed from {} to {}", from, to)
+            }
+            Change::RangeChanged { from, to } => write!(
+                f,
+                "range changed from {} to {}",
+                fmt_duration(from),
+                fmt_duration(to)
+            ),
+            Change::ResolutionChanged { from, to } => write!(
+                f,
+                "resolution changed from {} to {}",
+                fmt_duration(from),
+                fmt_duration(to)
+            ),
+            Change::OffsetChanged { from, to } => write!(
+                f,
+                "offset changed from {} to {}",
+                fmt_duration(from),
+                fmt_duration(to)
+            ),
+            Change::AtChanged { from, to } => {
+                write!(f, "@ changed from {} to {}", fmt_at(from), fmt_at(to))
+            }
+            Change::FunctionChanged { from, to } => {
+                write!(f, "function changed from {} to {}", from, to)
+            }
+            Change::GroupingChanged { from, to } => write!(
+                f,
+                "grouping changed from {} to {}",
+                fmt_grouping(from),
+                fmt_grouping(to)
+            ),
+            Change::GroupingLabelAdded(l) => write!(f, "grouping label {} added", l),
+            Change::GroupingLabelRemoved(l) => write!(f, "grouping label {} removed", l),
+            Change::OperatorChanged { from, to } => {
+                write!(f, "operator changed from {} to {}", from, to)
+            }
+            Change::ArgumentAdded(e) => write!(f, "argument {} added", e),
+            Change::ArgumentRemoved(e) => write!(f, "argument {} removed", e),
+            Change::NumberChanged { from, to } => {
+                write!(f, "number changed from {} to {}", from, to)
+            }
+            Change::StringChanged { from, to } => {
+                write!(f, "string changed from {:?} to {:?}", from, to)
+            }
+            Change::Wrapped(outer) => write!(f, "wrapped in {}", outline(outer)),
+            Change::Unwrapped(outer) => write!(f, "unwrapped from {}", outline(outer)),
+            Change::Replaced { from, to } => write!(f, "replaced {} with {}", from, to),
+        }
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.old_path == self.new_path {
+            write!(f, "{}: {}", self.old_path, self.change)
+        } else {
+            write!(f, "{} -> {}: {}", self.old_path, self.new_path, self.change)
+        }
+    }
+}
+
+/// One line per edit, e.g. `$.args[0]: range changed from 5m to 1m`.
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.edits.is_empty() {
+            return write!(f, "no changes");
+        }
+        for (i, edit) in self.edits.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", edit)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn rendered(old: &str, new: &str) -> String {
+        let (_, old) = parse_expr(old).unwrap();
+        let (_, new) = parse_expr(new).unwrap();
+        diff(&old, &new).to_string()
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        assert_eq!(
+            rendered(
+                r#"sum(rate(a{x="1", y="2"}[5m])) by (job, le)"#,
+                r#"sum by (le, job) (rate(a{y="2", x="1"}[5m]))"#
+            ),
+            "no changes"
+        );
+    }
+
+    #[test]
+    fn test_diff_leaf_changes() {
+        assert_eq!(
+            rendered(
+                r#"sum by (job) (rate(a{env="prod", x="1"}[5m])) > 10"#,
+                r#"sum by (job, env) (irate(a{env="dev", y="1"}[1m] offset 1h)) > bool 20"#
+            ),
+            [
+                "$: operator changed from > to > bool",
+                "$.lhs: grouping label env added",
+                "$.lhs.args[0]: function changed from rate to irate",
+                r#"$.lhs.args[0].args[0]: matcher changed from env="prod" to env="dev""#,
+                r#"$.lhs.args[0].args[0]: matcher x="1" removed"#,
+                r#"$.lhs.args[0].args[0]: matcher y="1" added"#,
+                "$.lhs.args[0].args[0]: range changed from 5m to 1m",
+                "$.lhs.args[0].args[0]: offset changed from none to 1h",
+                "$.rhs: number changed from 10 to 20",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_diff_wrapping() {
+        let (_, old) = parse_expr("rate(a[5m]) / b").unwrap();
+        let (_, new) = parse_expr("sum by (job) (rate(a[5m])) / b").unwrap();
+        let d = diff(&old, &new);
+
+        assert_eq!(d.edits.len(), 1);
+        assert_eq!(d.edits[0].old_path.to_string(), "$.lhs");
+        assert_eq!(d.edits[0].new_path.to_string(), "$.lhs.args[0]");
+        assert_eq!(
+            d.to_string(),
+            "$.lhs -> $.lhs.args[0]: wrapped in sum by (job) (…)"
+        );
+        assert_eq!(
+            diff(&new, &old).to_string(),
+            "$.lhs.args[0] -> $.lhs: unwrapped from sum by (job) (…)"
+        );
+        assert_eq!(rendered("a", "1"), "$: replaced a with 1");
+    }
+}
@@ -0,0 +1,383 @@
+use super::functions::sort;
+use super::over_time::{kahan_sum, mean, quantile, variance};
+use super::{EngineError, Evaluator};
+use crate::ast::{AggregationModifier, AggregationModifierAction, FunCall};
+use crate::value::{self, is_label_name, Labels, Sample, Value, METRIC_NAME};
+use std::collections::HashMap;
+
+/// Samples of one output series of an aggregation.
+struct Group {
+    labels: Labels,
+    samples: Vec<Sample>,
+}
+
+/// Splits `v` into groups by the `by`/`without` labels of `modifier`, in order
+/// of first appearance. Grouping always drops the metric name unless `by`
+/// names it explicitly.
+fn group(modifier: Option<&AggregationModifier>, v: value::Vector) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut index: HashMap<u64, usize> = HashMap::new();
+
+    let dropped: Vec<&str> = match modifier {
+        Some(m) if m.action == AggregationModifierAction::Without => m
+            .labels
+            .iter()
+            .map(|l| l.as_str())
+            .chain(Some(METRIC_NAME))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    for s in v {
+        let key = match modifier {
+            Some(m) if m.action == AggregationModifierAction::By => {
+                s.metric.hash_for_labels(&m.labels)
+            }
+            Some(m) => s.metric.hash_without_labels(&m.labels),
+            None => 0,
+        };
+
+        match index.get(&key) {
+            Some(&i) => groups[i].samples.push(s),
+            None => {
+                let labels = match modifier {
+                    Some(m) if m.action == AggregationModifierAction::By => {
+                        s.metric.keep_labels(&m.labels)
+                    }
+                    Some(_) => s.metric.drop_labels(&dropped),
+                    None => Labels::default(),
+                };
+                index.insert(key, groups.len());
+                groups.push(Group {
+                    labels,
+                    samples: vec![s],
+                });
+            }
+        }
+    }
+    groups
+}
+
+/// Prometheus replaces a NaN extreme by any later number, so NaN only wins if
+/// all values are NaN.
+fn extreme(values: &[f64], max: bool) -> f64 {
+    values.iter().fold(f64::NAN, |acc, &v| {
+        if acc.is_nan() || (max && v > acc) || (!max && v < acc) {
+            v
+        } else {
+            acc
+        }
+    })
+}
+
+fn aggregate(name: &str, values: &[f64]) -> Option<f64> {
+    Some(match name {
+        "sum" => kahan_sum(values.iter().copied()),
+        "avg" => mean(values.iter().copied()),
+        "count" => values.len() as f64,
+        "group" => 1.0,
+        "min" => extreme(values, false),
+        "max" => extreme(values, true),
+        "stddev" => variance(values.iter().copied()).sqrt(),
+        "stdvar" => variance(values.iter().copied()),
+        _ => return None,
+    })
+}
+
+/// Whether the aggregation takes a parameter before the vector.
+fn has_parameter(name: &str) -> bool {
+    matches!(
+        name,
+        "topk" | "bottomk" | "quantile" | "count_values" | "limitk" | "limit_ratio"
+    )
+}
+
+/// The `k` of `topk`, `bottomk` and `limitk`, truncated like Prometheus does.
+fn count_parameter(k: f64) -> Result<usize, EngineError> {
+    if k.is_nan() || k >= i64::MAX as f64 || k < i64::MIN as f64 {
+        return Err(EngineError::InvalidArgument(format!(
+            "Scalar value {} overflows int64",
+            k
+        )));
+    }
+    Ok(if k < 1.0 { 0 } else { k as usize })
+}
+
+/// Whether `labels` is among the series `limit_ratio` keeps. Series are placed
+/// in `[0, 1)` by their fingerprint: a positive ratio keeps those below it, a
+/// negative one those at or above `1 + ratio`, so that `limit_ratio(r, x)` and
+/// `limit_ratio(-(1 - r), x)` split `x` in two.
+fn in_ratio(labels: &Labels, ratio: f64) -> bool {
+    let offset = labels.fingerprint() as f64 / u64::MAX as f64;
+    if ratio >= 0.0 {
+        offset < ratio
+    } else {
+        offset >= 1.0 + ratio
+    }
+}
+
+/// A sample value as a label value, spelled the way Prometheus formats it.
+fn format_value(v: f64) -> String {
+    if v == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if v == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else {
+        v.to_string()
+    }
+}
+
+/// `count_values`: the value label joins the grouping labels of `by`, while
+/// `without` is applied as written.
+fn count_values(
+    f: &FunCall,
+    label: &str,
+    v: value::Vector,
+    ts: i64,
+) -> Result<value::Vector, EngineError> {
+    if !is_label_name(label) {
+        return Err(EngineError::InvalidArgument(format!(
+            "invalid label name {:?}",
+            label
+        )));
+    }
+    let modifier = match f.aggregation.clone() {
+        Some(mut m) if m.action == AggregationModifierAction::By => {
+            m.labels.push(label.to_owned());
+            m
+        }
+        Some(m) => m,
+        None => AggregationModifier {
+            action: AggregationModifierAction::By,
+            labels: vec![label.to_owned()],
+        },
+    };
+
+    let v = v
+        .into_iter()
+        .map(|mut s| {
+            s.metric.set(label, &format_value(s.v));
+            s
+        })
+        .collect();
+    Ok(group(Some(&modifier), v)
+        .into_iter()
+        .map(|g| Sample {
+            metric: g.labels,
+            t: ts,
+            v: g.samples.len() as f64,
+        })
+        .collect())
+}
+
+pub(super) fn eval(ev: &mut Evaluator<'_>, f: &FunCall, ts: i64) -> Result<Value, EngineError> {
+    let ctx = format!("aggregation expression {}", f.name);
+    let name = f.name.as_str();
+    let (param, arg) = match (has_parameter(name), f.args.as_slice()) {
+        (true, [param, arg]) => (Some(param), arg),
+        (false, [arg]) => (None, arg),
+        (with_param, args) => {
+            return Err(EngineError::ArgumentCount {
+                func: f.name.clone(),
+                expected: if with_param { "2" } else { "1" }.to_owned(),
+                got: args.len(),
+            })
+        }
+    };
+
+    if name == "count_values" {
+        let label = ev.eval_string(param.unwrap(), ts, &ctx)?;
+        let v = ev.eval_vector(arg, ts, &ctx)?;
+        return Ok(Value::Vector(count_values(f, &label, v, ts)?));
+    }
+
+    let param = match param {
+        Some(p) => ev.eval_scalar(p, ts, &ctx)?,
+        None => f64::NAN,
+    };
+    let k = match name {
+        "topk" | "bottomk" | "limitk" => count_parameter(param)?,
+        _ => 0,
+    };
+    if name == "limit_ratio" && param.is_nan() {
+        return Err(EngineError::InvalidArgument(format!(
+            "Ratio value {} is NaN",
+            param
+        )));
+    }
+    let v = ev.eval_vector(arg, ts, &ctx)?;
+
+    let mut out = Vec::new();
+    for g in group(f.aggregation.as_ref(), v) {
+        // Selections keep the samples with all their labels.
+        match name {
+            "topk" | "bottomk" => {
+                let mut samples = sort(g.samples, name == "topk");
+                samples.truncate(k);
+                out.extend(samples);
+                continue;
+            }
+            "limitk" => {
+                out.extend(g.samples.into_iter().take(k));
+                continue;
+            }
+            "limit_ratio" => {
+                let ratio = param.clamp(-1.0, 1.0);
+                out.extend(g.samples.into_iter().filter(|s| in_ratio(&s.metric, ratio)));
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut values: Vec<f64> = g.samples.iter().map(|s| s.v).collect();
+        let v = if name == "quantile" {
+            quantile(param, &mut values)
+        } else {
+            aggregate(name, &values).ok_or_else(|| EngineError::UnknownFunction(f.name.clone()))?
+        };
+        out.push(Sample {
+            metric: g.labels,
+            t: ts,
+            v,
+        });
+    }
+    Ok(Value::Vector(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{query, storage};
+    use crate::engine::Engine;
+    use crate::parse_expr;
+    use crate::value::Value;
+
+    #[test]
+    fn test_aggregations() {
+        let s = storage(&[
+            (r#"x{job="a", instance="1"}"#, &[1.0]),
+            (r#"x{job="a", instance="2"}"#, &[3.0]),
+            (r#"x{job="b", instance="1"}"#, &[f64::NAN]),
+            (r#"x{job="b", instance="2"}"#, &[2.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "sum by (job) (x)", 0).unwrap(),
+            vec![r#"{job="a"} 4"#, r#"{job="b"} NaN"#]
+        );
+        assert_eq!(
+            query(&s, "max without (job) (x)", 0).unwrap(),
+            vec![r#"{instance="1"} 1"#, r#"{instance="2"} 3"#]
+        );
+        assert_eq!(
+            query(&s, "min by (job) (x)", 0).unwrap(),
+            vec![r#"{job="a"} 1"#, r#"{job="b"} 2"#]
+        );
+        assert_eq!(query(&s, "count(x)", 0).unwrap(), vec!["{} 4"]);
+        assert_eq!(
+            query(&s, r#"avg by (instance) (x{job="a"})"#, 0).unwrap(),
+            vec![r#"{instance="1"} 1"#, r#"{instance="2"} 3"#]
+        );
+        assert!(query(&s, "sum(x)", 10 * 60 * 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parameterised_aggregations() {
+        let s = storage(&[
+            (r#"y{job="a", i="1"}"#, &[1.0]),
+            (r#"y{job="a", i="2"}"#, &[2.0]),
+            (r#"y{job="a", i="3"}"#, &[4.0]),
+            (r#"y{job="b", i="1"}"#, &[f64::NAN]),
+            (r#"y{job="b", i="2"}"#, &[5.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "topk by (job) (1, y)", 0).unwrap(),
+            vec![
+                r#"{__name__="y", i="2", job="b"} 5"#,
+                r#"{__name__="y", i="3", job="a"} 4"#,
+            ]
+        );
+        assert_eq!(
+            query(&s, "bottomk by (job) (1, y)", 0).unwrap(),
+            vec![
+                r#"{__name__="y", i="1", job="a"} 1"#,
+                r#"{__name__="y", i="2", job="b"} 5"#,
+            ]
+        );
+        assert!(query(&s, "topk(0, y)", 0).unwrap().is_empty());
+        assert_eq!(
+            query(&s, r#"stdvar(y{job="a"})"#, 0).unwrap(),
+            vec![format!("{{}} {}", 1.5555555555555554)]
+        );
+        assert_eq!(
+            query(&s, r#"quantile(0.5, y{job="a"})"#, 0).unwrap(),
+            vec!["{} 2"]
+        );
+        assert_eq!(
+            query(&s, r#"count_values without (i) ("v", y)"#, 0).unwrap(),
+            vec![
+                r#"{job="a", v="1"} 1"#,
+                r#"{job="a", v="2"} 1"#,
+                r#"{job="a", v="4"} 1"#,
+                r#"{job="b", v="5"} 1"#,
+                r#"{job="b", v="NaN"} 1"#,
+            ]
+        );
+        assert_eq!(
+            query(&s, r#"count_values without (v) ("v", y{job="b"})"#, 0).unwrap(),
+            vec![r#"{i="1", job="b"} 1"#, r#"{i="2", job="b"} 1"#]
+        );
+        assert_eq!(
+            query(&s, r#"count_values("job", y)"#, 0).unwrap(),
+            vec![
+                r#"{job="1"} 1"#,
+                r#"{job="2"} 1"#,
+                r#"{job="4"} 1"#,
+                r#"{job="5"} 1"#,
+                r#"{job="NaN"} 1"#,
+            ]
+        );
+        assert_eq!(query(&s, "limitk by (job) (1, y)", 0).unwrap().len(), 2);
+        assert_eq!(query(&s, "limit_ratio(1, y)", 0).unwrap().len(), 5);
+        assert_eq!(
+            query(&s, "limit_ratio(0.5, y)", 0).unwrap().len()
+                + query(&s, "limit_ratio(-0.5, y)", 0).unwrap().len(),
+            5
+        );
+
+        assert_eq!(
+            query(&s, "topk(NaN, y)", 0).unwrap_err().to_string(),
+            "Scalar value NaN overflows int64"
+        );
+        assert_eq!(
+            query(&s, r#"count_values("a-b", y)"#, 0)
+                .unwrap_err()
+                .to_string(),
+            r#"invalid label name "a-b""#
+        );
+        assert_eq!(
+            query(&s, "sum(1, y)", 0).unwrap_err().to_string(),
+            r#"expected 1 argument(s) in call to "sum", got 2"#
+        );
+    }
+
+    #[test]
+    fn test_topk_order() {
+        let s = storage(&[
+            (r#"y{i="1"}"#, &[1.0]),
+            (r#"y{i="2"}"#, &[f64::NAN]),
+            (r#"y{i="3"}"#, &[4.0]),
+            (r#"y{i="4"}"#, &[2.0]),
+        ]);
+        let values = |q: &str| match Engine::new()
+            .instant_query(&s, &parse_expr(q).unwrap().1, 0)
+            .unwrap()
+        {
+            Value::Vector(v) => v.iter().map(|s| s.v.to_string()).collect::<Vec<_>>(),
+            v => panic!("not a vector: {}", v),
+        };
+
+        assert_eq!(values("topk(3, y)"), vec!["4", "2", "1"]);
+        assert_eq!(values("bottomk(4, y)"), vec!["1", "2", "4", "NaN"]);
+    }
+}
@@ -0,0 +1,438 @@
+use super::{check_unique, type_name, EngineError, Evaluator};
+use crate::ast::{
+    BinaryExpr, BinaryModifier, BinaryModifierAction, BinaryModifierGroupSide, BinaryOp,
+};
+use crate::value::{self, Labels, Sample, Scalar, Value};
+use std::collections::{HashMap, HashSet};
+
+fn is_comparison(op: &BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Equal(..)
+            | BinaryOp::NotEqual(..)
+            | BinaryOp::GreaterThan(..)
+            | BinaryOp::LessThan(..)
+            | BinaryOp::GreaterEqual(..)
+            | BinaryOp::LessEqual(..)
+    )
+}
+
+fn is_set_operator(op: &BinaryOp) -> bool {
+    matches!(op, BinaryOp::And(_) | BinaryOp::Or(_) | BinaryOp::Unless(_))
+}
+
+/// Applies an arithmetic or comparison operator. Comparisons give `1` or `0`,
+/// and whether the pair is kept.
+fn apply(op: &BinaryOp, l: f64, r: f64) -> (f64, bool) {
+    let cmp = |keep: bool| (if keep { 1.0 } else { 0.0 }, keep);
+    match op {
+        BinaryOp::Add(_) => (l + r, true),
+        BinaryOp::Sub(_) => (l - r, true),
+        BinaryOp::Mul(_) => (l * r, true),
+        BinaryOp::Div(_) => (l / r, true),
+        BinaryOp::Mod(_) => (l % r, true),
+        BinaryOp::Power(_) => (l.powf(r), true),
+        BinaryOp::Equal(..) => cmp(l == r),
+        BinaryOp::NotEqual(..) => cmp(l != r),
+        BinaryOp::GreaterThan(..) => cmp(l > r),
+        BinaryOp::LessThan(..) => cmp(l < r),
+        BinaryOp::GreaterEqual(..) => cmp(l >= r),
+        BinaryOp::LessEqual(..) => cmp(l <= r),
+        BinaryOp::And(_) | BinaryOp::Or(_) | BinaryOp::Unless(_) => (l, true),
+    }
+}
+
+/// Result of one element pair: arithmetic and `bool` comparisons give the
+/// computed value, filtering comparisons the vector-side value `kept`.
+fn element(op: &BinaryOp, l: f64, r: f64, kept: f64) -> Option<f64> {
+    let (v, keep) = apply(op, l, r);
+    if !is_comparison(op) || op.is_bool() {
+        Some(v)
+    } else if keep {
+        Some(kept)
+    } else {
+        None
+    }
+}
+
+/// Whether the result keeps the metric name: only filtering comparisons do.
+fn keeps_name(op: &BinaryOp) -> bool {
+    is_comparison(op) && !op.is_bool()
+}
+
+fn vector_scalar(op: &BinaryOp, v: value::Vector, scalar: f64, swap: bool) -> value::Vector {
+    v.into_iter()
+        .filter_map(|s| {
+            let (l, r) = if swap { (scalar, s.v) } else { (s.v, scalar) };
+            let v = element(op, l, r, s.v)?;
+            let metric = if keeps_name(op) {
+                s.metric
+            } else {
+                s.metric.without_metric_name()
+            };
+            Some(Sample { metric, t: s.t, v })
+        })
+        .collect()
+}
+
+/// Hash of the labels that `on`/`ignoring` match samples on. Without a
+/// modifier, all labels but the metric name are matched.
+fn signature(m: Option<&BinaryModifier>, labels: &Labels) -> u64 {
+    match m {
+        Some(m) if m.action == BinaryModifierAction::On => labels.hash_for_labels(&m.labels),
+        Some(m) => labels.hash_without_labels(&m.labels),
+        None => labels.hash_without_labels(&[] as &[&str]),
+    }
+}
+
+/// The labels `signature` hashes, for error messages.
+fn match_labels(m: Option<&BinaryModifier>, labels: &Labels) -> Labels {
+    match m {
+        Some(m) if m.action == BinaryModifierAction::On => labels.keep_labels(&m.labels),
+        Some(m) => labels.drop_labels(&m.labels).without_metric_name(),
+        None => labels.without_metric_name(),
+    }
+}
+
+/// Labels of a result sample: those of the "many" side, narrowed to the
+/// matching labels for one-to-one matching, plus the `group_x` labels copied
+/// from the "one" side.
+fn result_metric(op: &BinaryOp, many: &Labels, one: &Labels) -> Labels {
+    let mut metric = if keeps_name(op) {
+        many.clone()
+    } else {
+        many.without_metric_name()
+    };
+    match op.modifier() {
+        Some(m) if m.group.is_some() => {
+            for name in &m.group.as_ref().unwrap().labels {
+                metric.set(name, one.get(name).unwrap_or(""));
+            }
+        }
+        Some(m) if m.action == BinaryModifierAction::On => metric = metric.keep_labels(&m.labels),
+        Some(m) => metric = metric.drop_labels(&m.labels),
+        None => {}
+    }
+    metric
+}
+
+/// Arithmetic and comparison between vectors: one-to-one matching, or
+/// many-to-one and one-to-many with `group_left` and `group_right`.
+fn vector_vector(
+    op: &BinaryOp,
+    lhs: value::Vector,
+    rhs: value::Vector,
+) -> Result<value::Vector, EngineError> {
+    let m = op.modifier();
+    let group = m.and_then(|m| m.group.as_ref());
+    if lhs.is_empty() || rhs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Swap sides for one-to-many matching so that the right is always "one".
+    let swapped = matches!(group, Some(g) if g.side == BinaryModifierGroupSide::Right);
+    let (many, one) = if swapped { (rhs, lhs) } else { (lhs, rhs) };
+
+    let mut right: HashMap<u64, &Sample> = HashMap::with_capacity(one.len());
+    for s in &one {
+        if let Some(dup) = right.insert(signature(m, &s.metric), s) {
+            return Err(EngineError::InvalidArgument(format!(
+                "found duplicate series for the match group {} on the {} hand-side of the operation: [{}, {}];\
+                 many-to-many matching not allowed: matching labels must be unique on one side",
+                match_labels(m, &s.metric),
+                if swapped { "left" } else { "right" },
+                s.metric,
+                dup.metric
+            )));
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut matched: HashMap<u64, HashSet<u64>> = HashMap::new();
+    for s in many {
+        let sig = signature(m, &s.metric);
+        let r = match right.get(&sig) {
+            Some(r) => r,
+            None => continue,
+        };
+        let (l, r_v) = if swapped { (r.v, s.v) } else { (s.v, r.v) };
+        let v = match element(op, l, r_v, l) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let metric = result_metric(op, &s.metric, &r.metric);
+        // One-to-one matching allows one result per signature, grouping one
+        // per result label set.
+        let results = matched.entry(sig).or_default();
+        if group.is_none() && !results.is_empty() {
+            return Err(EngineError::InvalidArgument(
+                "multiple matches for labels: many-to-one matching must be explicit (group_left/group_right)"
+                    .to_owned(),
+            ));
+        }
+        if !results.insert(metric.fingerprint()) {
+            return Err(EngineError::InvalidArgument(
+                "multiple matches for labels: grouping labels must ensure unique matches"
+                    .to_owned(),
+            ));
+        }
+        out.push(Sample { metric, t: s.t, v });
+    }
+    Ok(out)
+}
+
+/// `and`, `or` and `unless`, which keep samples whole and match many-to-many.
+fn set_operation(op: &BinaryOp, lhs: value::Vector, rhs: value::Vector) -> value::Vector {
+    let m = op.modifier();
+    let signatures =
+        |v: &value::Vector| -> HashSet<u64> { v.iter().map(|s| signature(m, &s.metric)).collect() };
+    match op {
+        BinaryOp::And(_) => {
+            let right = signatures(&rhs);
+            lhs.into_iter()
+                .filter(|s| right.contains(&signature(m, &s.metric)))
+                .collect()
+        }
+        BinaryOp::Unless(_) => {
+            let right = signatures(&rhs);
+            lhs.into_iter()
+                .filter(|s| !right.contains(&signature(m, &s.metric)))
+                .collect()
+        }
+        _ => {
+            let left = signatures(&lhs);
+            let mut out = lhs;
+            out.extend(
+                rhs.into_iter()
+                    .filter(|s| !left.contains(&signature(m, &s.metric))),
+            );
+            out
+        }
+    }
+}
+
+/// Checks the modifiers that the parser lets through but Prometheus rejects.
+fn check_modifier(op: &BinaryOp) -> Result<(), EngineError> {
+    let m = match op.modifier() {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    if let Some(group) = &m.group {
+        if is_set_operator(op) {
+            return Err(EngineError::InvalidArgument(format!(
+                "no grouping allowed for {:?} operation",
+                op.symbol()
+            )));
+        }
+        if m.action == BinaryModifierAction::On {
+            if let Some(name) = group.labels.iter().find(|l| m.labels.contains(l)) {
+                return Err(EngineError::InvalidArgument(format!(
+                    "label {:?} must not occur in ON and GROUP clause at once",
+                    name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn eval(ev: &mut Evaluator<'_>, b: &BinaryExpr, ts: i64) -> Result<Value, EngineError> {
+    check_modifier(&b.op)?;
+    let lhs = ev.eval(&b.lhs, ts)?;
+    let rhs = ev.eval(&b.rhs, ts)?;
+
+    let out = match (lhs, rhs) {
+        (Value::Scalar(_), _) | (_, Value::Scalar(_)) if is_set_operator(&b.op) => {
+            return Err(EngineError::InvalidArgument(format!(
+                "set operator {:?} not allowed in binary scalar expression",
+                b.op.symbol()
+            )));
+        }
+        (Value::Scalar(l), Value::Scalar(r)) => {
+            if is_comparison(&b.op) && !b.op.is_bool() {
+                return Err(EngineError::InvalidArgument(
+                    "comparisons between scalars must use BOOL modifier".to_owned(),
+                ));
+            }
+            return Ok(Value::Scalar(Scalar {
+                t: ts,
+                v: apply(&b.op, l.v, r.v).0,
+            }));
+        }
+        (Value::Vector(l), Value::Scalar(r)) => vector_scalar(&b.op, l, r.v, false),
+        (Value::Scalar(l), Value::Vector(r)) => vector_scalar(&b.op, r, l.v, true),
+        (Value::Vector(l), Value::Vector(r)) if is_set_operator(&b.op) => {
+            set_operation(&b.op, l, r)
+        }
+        (Value::Vector(l), Value::Vector(r)) => vector_vector(&b.op, l, r)?,
+        (l, r) => {
+            let bad = if matches!(l, Value::Scalar(_) | Value::Vector(_)) {
+                r
+            } else {
+                l
+            };
+            return Err(EngineError::TypeMismatch {
+                context: "binary expression".to_owned(),
+                expected: "scalar or instant vector",
+                got: type_name(&bad),
+            });
+        }
+    };
+
+    check_unique(&out)?;
+    Ok(Value::Vector(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{query, storage};
+
+    #[test]
+    fn test_scalar_operands() {
+        let s = storage(&[(r#"x{a="1"}"#, &[1.0]), (r#"x{a="2"}"#, &[5.0])]);
+
+        assert_eq!(query(&s, "2 ^ 3 ^ 2", 0).unwrap(), vec!["512"]);
+        assert_eq!(query(&s, "7 % 4 - 1 / 0", 0).unwrap(), vec!["-inf"]);
+        assert_eq!(query(&s, "1 > bool 0", 0).unwrap(), vec!["1"]);
+        assert!(query(&s, "1 > 0", 0).is_err());
+        assert_eq!(
+            query(&s, "x * 2", 0).unwrap(),
+            vec![r#"{a="1"} 2"#, r#"{a="2"} 10"#]
+        );
+        assert_eq!(
+            query(&s, "3 > x", 0).unwrap(),
+            vec![r#"{__name__="x", a="1"} 1"#]
+        );
+        assert_eq!(
+            query(&s, "x > bool 3", 0).unwrap(),
+            vec![r#"{a="1"} 0"#, r#"{a="2"} 1"#]
+        );
+    }
+
+    #[test]
+    fn test_vector_operands() {
+        let s = storage(&[
+            (r#"x{a="1"}"#, &[1.0]),
+            (r#"x{a="2"}"#, &[5.0]),
+            (r#"y{a="1"}"#, &[3.0]),
+            (r#"y{a="3"}"#, &[3.0]),
+        ]);
+
+        assert_eq!(query(&s, "x + y", 0).unwrap(), vec![r#"{a="1"} 4"#]);
+        assert_eq!(
+            query(&s, "y >= x", 0).unwrap(),
+            vec![r#"{__name__="y", a="1"} 3"#]
+        );
+        assert_eq!(
+            query(&s, "x + y[1m]", 0).unwrap_err().to_string(),
+            "expected type scalar or instant vector in binary expression, got range vector"
+        );
+    }
+
+    #[test]
+    fn test_vector_matching() {
+        let s = storage(&[
+            (r#"req{job="a", instance="1", code="200"}"#, &[4.0]),
+            (r#"req{job="a", instance="1", code="500"}"#, &[1.0]),
+            (r#"req{job="a", instance="2", code="200"}"#, &[6.0]),
+            (r#"up{job="a", instance="1", version="1.0"}"#, &[1.0]),
+            (r#"up{job="a", instance="2", version="2.0"}"#, &[2.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, r#"req{code="200"} * on(instance) up"#, 0).unwrap(),
+            vec![r#"{instance="1"} 4"#, r#"{instance="2"} 12"#]
+        );
+        assert_eq!(
+            query(&s, r#"req{code="200"} / ignoring(code, version) up"#, 0).unwrap(),
+            vec![
+                r#"{instance="1", job="a"} 4"#,
+                r#"{instance="2", job="a"} 3"#
+            ]
+        );
+        assert_eq!(
+            query(&s, "req * on(instance) group_left(version) up", 0).unwrap(),
+            vec![
+                r#"{code="200", instance="1", job="a", version="1.0"} 4"#,
+                r#"{code="200", instance="2", job="a", version="2.0"} 12"#,
+                r#"{code="500", instance="1", job="a", version="1.0"} 1"#,
+            ]
+        );
+        // Filtering keeps the "one"-side value and the "many"-side labels.
+        assert_eq!(
+            query(&s, "up >= on(instance) group_right() req", 0).unwrap(),
+            vec![r#"{__name__="req", code="500", instance="1", job="a"} 1"#]
+        );
+        assert_eq!(
+            query(&s, "up >= bool on(instance) group_right() req", 0).unwrap(),
+            vec![
+                r#"{code="200", instance="1", job="a"} 0"#,
+                r#"{code="200", instance="2", job="a"} 0"#,
+                r#"{code="500", instance="1", job="a"} 1"#,
+            ]
+        );
+
+        assert_eq!(
+            query(&s, "req * on(instance) up", 0).unwrap_err().to_string(),
+            "multiple matches for labels: many-to-one matching must be explicit (group_left/group_right)"
+        );
+        assert_eq!(
+            query(&s, "up * on(instance) req", 0)
+                .unwrap_err()
+                .to_string(),
+            r#"found duplicate series for the match group {instance="1"} on the right hand-side of the operation: [{__name__="req", code="500", instance="1", job="a"}, {__name__="req", code="200", instance="1", job="a"}];many-to-many matching not allowed: matching labels must be unique on one side"#
+        );
+        assert_eq!(
+            query(&s, "req * on(job) group_left() up", 0)
+                .unwrap_err()
+                .to_string(),
+            r#"found duplicate series for the match group {job="a"} on the right hand-side of the operation: [{__name__="up", instance="2", job="a", version="2.0"}, {__name__="up", instance="1", job="a", version="1.0"}];many-to-many matching not allowed: matching labels must be unique on one side"#
+        );
+        assert_eq!(
+            query(&s, "req * on(instance) group_left(instance) up", 0)
+                .unwrap_err()
+                .to_string(),
+            r#"label "instance" must not occur in ON and GROUP clause at once"#
+        );
+    }
+
+    #[test]
+    fn test_set_operators() {
+        let s = storage(&[
+            (r#"x{a="1"}"#, &[1.0]),
+            (r#"x{a="2"}"#, &[2.0]),
+            (r#"y{a="2"}"#, &[3.0]),
+            (r#"y{a="3"}"#, &[4.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "x and y", 0).unwrap(),
+            vec![r#"{__name__="x", a="2"} 2"#]
+        );
+        assert_eq!(
+            query(&s, "x unless y", 0).unwrap(),
+            vec![r#"{__name__="x", a="1"} 1"#]
+        );
+        assert_eq!(
+            query(&s, "x or y", 0).unwrap(),
+            vec![
+                r#"{__name__="x", a="1"} 1"#,
+                r#"{__name__="x", a="2"} 2"#,
+                r#"{__name__="y", a="3"} 4"#,
+            ]
+        );
+        assert_eq!(query(&s, "x and on() y", 0).unwrap().len(), 2);
+        assert!(query(&s, "x unless ignoring(a) y", 0).unwrap().is_empty());
+        assert_eq!(
+            query(&s, "x and 1", 0).unwrap_err().to_string(),
+            r#"set operator "and" not allowed in binary scalar expression"#
+        );
+        assert_eq!(
+            query(&s, "x or on(a) group_left() y", 0)
+                .unwrap_err()
+                .to_string(),
+            r#"no grouping allowed for "or" operation"#
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use crate::storage::StorageError;
+use crate::value::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EngineError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("unknown function with name {0:?}")]
+    UnknownFunction(String),
+    #[error("expected {expected} argument(s) in call to {func:?}, got {got}")]
+    ArgumentCount {
+        func: String,
+        expected: String,
+        got: usize,
+    },
+    #[error("expected type {expected} in {context}, got {got}")]
+    TypeMismatch {
+        context: String,
+        expected: &'static str,
+        got: &'static str,
+    },
+    #[error("vector cannot contain metrics with the same labelset")]
+    DuplicateLabelSet,
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0} are not supported")]
+    Unsupported(String),
+}
+
+/// The type of `v` as Prometheus names it in error messages.
+pub(crate) fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Scalar(_) => "scalar",
+        Value::Vector(_) => "instant vector",
+        Value::Matrix(_) => "range vector",
+        Value::String(_) => "string",
+    }
+}
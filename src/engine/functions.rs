@@ -0,0 +1,805 @@
+use super::histogram::histogram_quantile;
+use super::over_time::{
+    changes, double_exponential_smoothing, linear_regression, over_time_function, quantile,
+};
+use super::rate::{extrapolated_rate, instant_value, resets};
+use super::{check_unique, EngineError, Evaluator};
+use crate::ast::{Expr, FunCall, LabelMatcherOp};
+use crate::value::{self, is_label_name, Labels, Point, Sample, Scalar, Value, METRIC_NAME};
+use regex::Regex;
+use std::cmp::Ordering;
+
+fn context(f: &FunCall) -> String {
+    format!("call to function {:?}", f.name)
+}
+
+/// Checks that `f` has between `min` and `max` arguments.
+fn arity(f: &FunCall, min: usize, max: Option<usize>) -> Result<(), EngineError> {
+    let got = f.args.len();
+    let expected = match max {
+        Some(max) if max == min && got != min => min.to_string(),
+        _ if got < min => format!("at least {}", min),
+        Some(max) if got > max => format!("at most {}", max),
+        _ => return Ok(()),
+    };
+    Err(EngineError::ArgumentCount {
+        func: f.name.clone(),
+        expected,
+        got,
+    })
+}
+
+fn map_values<F: Fn(f64) -> f64>(v: value::Vector, f: F) -> Value {
+    Value::Vector(
+        v.into_iter()
+            .map(|s| Sample {
+                metric: s.metric.without_metric_name(),
+                t: s.t,
+                v: f(s.v),
+            })
+            .collect(),
+    )
+}
+
+/// Applies `f` to the points of each series, giving a vector at `ts` without
+/// the series for which `f` gives `None`.
+fn map_series<F: Fn(&[Point]) -> Option<f64>>(m: value::Matrix, ts: i64, f: F) -> Value {
+    Value::Vector(
+        m.into_iter()
+            .filter_map(|s| {
+                Some(Sample {
+                    v: f(&s.points)?,
+                    metric: s.metric.without_metric_name(),
+                    t: ts,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// The larger of `a` and `b` like Go's `math.Max`: `+Inf` wins over NaN, and
+/// NaN over any other value.
+fn go_max(a: f64, b: f64) -> f64 {
+    if a == f64::INFINITY || b == f64::INFINITY {
+        f64::INFINITY
+    } else if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        a.max(b)
+    }
+}
+
+/// The smaller of `a` and `b` like Go's `math.Min`: `-Inf` wins over NaN, and
+/// NaN over any other value.
+fn go_min(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY || b == f64::NEG_INFINITY {
+        f64::NEG_INFINITY
+    } else if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        a.min(b)
+    }
+}
+
+fn sgn(v: f64) -> f64 {
+    if v > 0.0 {
+        1.0
+    } else if v < 0.0 {
+        -1.0
+    } else {
+        v
+    }
+}
+
+fn math_function(name: &str) -> Option<fn(f64) -> f64> {
+    Some(match name {
+        "abs" => f64::abs,
+        "ceil" => f64::ceil,
+        "floor" => f64::floor,
+        "exp" => f64::exp,
+        "sqrt" => f64::sqrt,
+        "ln" => f64::ln,
+        "log2" => f64::log2,
+        "log10" => f64::log10,
+        "sgn" => sgn,
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" => f64::tan,
+        "asin" => f64::asin,
+        "acos" => f64::acos,
+        "atan" => f64::atan,
+        "sinh" => f64::sinh,
+        "cosh" => f64::cosh,
+        "tanh" => f64::tanh,
+        "asinh" => f64::asinh,
+        "acosh" => f64::acosh,
+        "atanh" => f64::atanh,
+        "deg" => f64::to_degrees,
+        "rad" => f64::to_radians,
+        _ => return None,
+    })
+}
+
+/// Year, month (1-12), day of month (1-31), day of week (0 is Sunday), day of
+/// year (1-366), hour and minute of a Unix time in seconds, in UTC.
+fn civil(secs: f64) -> (i64, i64, i64, i64, i64, i64, i64) {
+    let secs = secs as i64;
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy_march = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy_march + 2) / 153;
+    let day = doy_march - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let cumulative = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let day_of_year = cumulative[month as usize - 1] + day + if leap && month > 2 { 1 } else { 0 };
+    let weekday = (days + 4).rem_euclid(7);
+
+    (
+        year,
+        month,
+        day,
+        weekday,
+        day_of_year,
+        rem / 3600,
+        rem % 3600 / 60,
+    )
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn date_function(name: &str, secs: f64) -> Option<f64> {
+    let (year, month, day, weekday, day_of_year, hour, minute) = civil(secs);
+    Some(match name {
+        "year" => year,
+        "month" => month,
+        "day_of_month" => day,
+        "day_of_week" => weekday,
+        "day_of_year" => day_of_year,
+        "days_in_month" => days_in_month(year, month),
+        "hour" => hour,
+        "minute" => minute,
+        _ => return None,
+    } as f64)
+}
+
+/// Labels `absent` gives its result: those of the selector's equality matchers.
+fn absent_labels(arg: &Expr) -> Labels {
+    let mut labels = Labels::default();
+    if let Expr::VectorExpr(v) = arg {
+        let mut seen = Vec::new();
+        for m in &v.label_matchers {
+            if m.op != LabelMatcherOp::Equal || m.name == METRIC_NAME {
+                continue;
+            }
+            if seen.contains(&m.name) {
+                // Conflicting equality matchers: the label cannot be known.
+                labels.remove(&m.name);
+            } else {
+                labels.set(&m.name, &crate::ast::unescape(&m.value));
+                seen.push(m.name.clone());
+            }
+        }
+    }
+    labels
+}
+
+fn label_replace(
+    v: value::Vector,
+    dst: &str,
+    replacement: &str,
+    src: &str,
+    regex: &str,
+) -> Result<value::Vector, EngineError> {
+    let regex = Regex::new(&format!("^(?s:{})$", regex)).map_err(|e| {
+        EngineError::InvalidArgument(format!(
+            "invalid regular expression in label_replace(): {}",
+            e
+        ))
+    })?;
+    if !is_label_name(dst) {
+        return Err(EngineError::InvalidArgument(format!(
+            "invalid destination label name in label_replace(): {}",
+            dst
+        )));
+    }
+
+    Ok(v.into_iter()
+        .map(|mut s| {
+            let value = s.metric.get(src).unwrap_or("").to_owned();
+            if let Some(captures) = regex.captures(&value) {
+                let mut out = String::new();
+                captures.expand(replacement, &mut out);
+                s.metric.set(dst, &out);
+            }
+            s
+        })
+        .collect())
+}
+
+fn label_join(
+    v: value::Vector,
+    dst: &str,
+    separator: &str,
+    src: &[String],
+) -> Result<value::Vector, EngineError> {
+    if !is_label_name(dst) {
+        return Err(EngineError::InvalidArgument(format!(
+            "invalid destination label name in label_join(): {}",
+            dst
+        )));
+    }
+
+    Ok(v.into_iter()
+        .map(|mut s| {
+            let values: Vec<&str> = src.iter().map(|l| s.metric.get(l).unwrap_or("")).collect();
+            let joined = values.join(separator);
+            s.metric.set(dst, &joined);
+            s
+        })
+        .collect())
+}
+
+/// Orders samples by value, NaN last in either direction like Prometheus.
+pub(super) fn sort(mut v: value::Vector, descending: bool) -> value::Vector {
+    v.sort_by(|a, b| match (a.v.is_nan(), b.v.is_nan()) {
+        (true, true) => std::cmp::Ordering::Equal,
+        (true, false) => std::cmp::Ordering::Greater,
+        (false, true) => std::cmp::Ordering::Less,
+        _ if descending => b.v.partial_cmp(&a.v).unwrap(),
+        _ => a.v.partial_cmp(&b.v).unwrap(),
+    });
+    v
+}
+
+/// Compares strings in natural order: runs of digits compare by their numeric
+/// value, so `a2` sorts before `a10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (x, y) = match (a.chars().next(), b.chars().next()) {
+            (Some(x), Some(y)) => (x, y),
+            (x, y) => return x.is_some().cmp(&y.is_some()),
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (da, db) = (digits(a), digits(b));
+            let (na, nb) = (
+                a[..da].trim_start_matches('0'),
+                b[..db].trim_start_matches('0'),
+            );
+            match na.len().cmp(&nb.len()).then_with(|| na.cmp(nb)) {
+                Ordering::Equal => {}
+                o => return o,
+            }
+            a = &a[da..];
+            b = &b[db..];
+        } else {
+            match x.cmp(&y) {
+                Ordering::Equal => {}
+                o => return o,
+            }
+            a = &a[x.len_utf8()..];
+            b = &b[y.len_utf8()..];
+        }
+    }
+}
+
+/// Sorts `v` by the values of `labels` in natural order, then by label set.
+fn sort_by_label(mut v: value::Vector, labels: &[String], descending: bool) -> value::Vector {
+    v.sort_by(|a, b| {
+        let o = labels
+            .iter()
+            .map(|l| {
+                natural_cmp(
+                    a.metric.get(l).unwrap_or_default(),
+                    b.metric.get(l).unwrap_or_default(),
+                )
+            })
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| a.metric.cmp(&b.metric));
+        if descending {
+            o.reverse()
+        } else {
+            o
+        }
+    });
+    v
+}
+
+pub(super) fn eval(ev: &mut Evaluator<'_>, f: &FunCall, ts: i64) -> Result<Value, EngineError> {
+    let ctx = context(f);
+    let name = f.name.as_str();
+
+    let out = match name {
+        _ if math_function(name).is_some() => {
+            arity(f, 1, Some(1))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            map_values(v, math_function(name).unwrap())
+        }
+        _ if over_time_function(name).is_some() => {
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            let f = over_time_function(name).unwrap();
+            map_series(m, ts, |points| Some(f(points)))
+        }
+        "time" => {
+            arity(f, 0, Some(0))?;
+            Value::Scalar(Scalar {
+                t: ts,
+                v: ts as f64 / 1000.0,
+            })
+        }
+        "pi" => {
+            arity(f, 0, Some(0))?;
+            Value::Scalar(Scalar {
+                t: ts,
+                v: std::f64::consts::PI,
+            })
+        }
+        "round" => {
+            arity(f, 1, Some(2))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let to_nearest = match f.args.get(1) {
+                Some(arg) => ev.eval_scalar(arg, ts, &ctx)?,
+                None => 1.0,
+            };
+            let inverse = 1.0 / to_nearest;
+            map_values(v, |v| (v * inverse + 0.5).floor() / inverse)
+        }
+        "clamp" => {
+            arity(f, 3, Some(3))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let min = ev.eval_scalar(&f.args[1], ts, &ctx)?;
+            let max = ev.eval_scalar(&f.args[2], ts, &ctx)?;
+            if max < min {
+                Value::Vector(Vec::new())
+            } else {
+                map_values(v, |v| go_min(go_max(v, min), max))
+            }
+        }
+        "clamp_min" | "clamp_max" => {
+            arity(f, 2, Some(2))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let bound = ev.eval_scalar(&f.args[1], ts, &ctx)?;
+            if name == "clamp_min" {
+                map_values(v, |v| go_max(v, bound))
+            } else {
+                map_values(v, |v| go_min(v, bound))
+            }
+        }
+        "scalar" => {
+            arity(f, 1, Some(1))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            Value::Scalar(Scalar {
+                t: ts,
+                v: if v.len() == 1 { v[0].v } else { f64::NAN },
+            })
+        }
+        "vector" => {
+            arity(f, 1, Some(1))?;
+            let v = ev.eval_scalar(&f.args[0], ts, &ctx)?;
+            Value::Vector(vec![Sample {
+                metric: Labels::default(),
+                t: ts,
+                v,
+            }])
+        }
+        "timestamp" => {
+            arity(f, 1, Some(1))?;
+            // Selectors give their samples' own timestamps, anything else the
+            // evaluation time.
+            let v = match &f.args[0] {
+                Expr::VectorExpr(v) if v.range.is_none() => ev.instant_selector(v, ts, true)?,
+                arg => ev.eval_vector(arg, ts, &ctx)?,
+            };
+            Value::Vector(
+                v.into_iter()
+                    .map(|s| Sample {
+                        metric: s.metric.without_metric_name(),
+                        t: ts,
+                        v: s.t as f64 / 1000.0,
+                    })
+                    .collect(),
+            )
+        }
+        "year" | "month" | "day_of_month" | "day_of_week" | "day_of_year" | "days_in_month"
+        | "hour" | "minute" => {
+            arity(f, 0, Some(1))?;
+            let v = match f.args.first() {
+                Some(arg) => ev.eval_vector(arg, ts, &ctx)?,
+                None => vec![Sample {
+                    metric: Labels::default(),
+                    t: ts,
+                    v: ts as f64 / 1000.0,
+                }],
+            };
+            Value::Vector(
+                v.into_iter()
+                    .filter(|s| s.v.is_finite())
+                    .map(|s| Sample {
+                        metric: s.metric.without_metric_name(),
+                        t: s.t,
+                        v: date_function(name, s.v).unwrap_or(f64::NAN),
+                    })
+                    .collect(),
+            )
+        }
+        "rate" | "increase" | "delta" | "irate" | "idelta" | "resets" => {
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            let (start, end) = ev.matrix_range(&f.args[0], ts);
+            map_series(m, ts, |points| match name {
+                "rate" => extrapolated_rate(points, start, end, true, true),
+                "increase" => extrapolated_rate(points, start, end, true, false),
+                "delta" => extrapolated_rate(points, start, end, false, false),
+                "irate" => instant_value(points, true),
+                "idelta" => instant_value(points, false),
+                _ => Some(resets(points)),
+            })
+        }
+        "changes" => {
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            map_series(m, ts, |points| Some(changes(points)))
+        }
+        "deriv" => {
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            map_series(m, ts, |points| match points.len() {
+                0 | 1 => None,
+                _ => Some(linear_regression(points, points[0].t).0),
+            })
+        }
+        "predict_linear" => {
+            arity(f, 2, Some(2))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            let duration = ev.eval_scalar(&f.args[1], ts, &ctx)?;
+            map_series(m, ts, |points| match points.len() {
+                0 | 1 => None,
+                _ => {
+                    let (slope, intercept) = linear_regression(points, ts);
+                    Some(slope * duration + intercept)
+                }
+            })
+        }
+        "double_exponential_smoothing" | "holt_winters" => {
+            arity(f, 3, Some(3))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            let sf = ev.eval_scalar(&f.args[1], ts, &ctx)?;
+            let tf = ev.eval_scalar(&f.args[2], ts, &ctx)?;
+            if !(sf > 0.0 && sf < 1.0) {
+                return Err(EngineError::InvalidArgument(format!(
+                    "invalid smoothing factor. Expected: 0 < sf < 1, got: {}",
+                    sf
+                )));
+            }
+            if !(tf > 0.0 && tf < 1.0) {
+                return Err(EngineError::InvalidArgument(format!(
+                    "invalid trend factor. Expected: 0 < tf < 1, got: {}",
+                    tf
+                )));
+            }
+            map_series(m, ts, |points| double_exponential_smoothing(points, sf, tf))
+        }
+        "histogram_quantile" => {
+            arity(f, 2, Some(2))?;
+            let q = ev.eval_scalar(&f.args[0], ts, &ctx)?;
+            let v = ev.eval_vector(&f.args[1], ts, &ctx)?;
+            Value::Vector(histogram_quantile(q, v, ts))
+        }
+        "last_over_time" => {
+            // Unlike other functions, keeps the metric name.
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            Value::Vector(
+                m.into_iter()
+                    .filter_map(|s| {
+                        let last = s.points.last()?;
+                        Some(Sample {
+                            v: last.v,
+                            metric: s.metric,
+                            t: ts,
+                        })
+                    })
+                    .collect(),
+            )
+        }
+        "quantile_over_time" => {
+            arity(f, 2, Some(2))?;
+            let q = ev.eval_scalar(&f.args[0], ts, &ctx)?;
+            let m = ev.eval_matrix(&f.args[1], ts, &ctx)?;
+            map_series(m, ts, |points| {
+                let mut values: Vec<f64> = points.iter().map(|p| p.v).collect();
+                Some(quantile(q, &mut values))
+            })
+        }
+        "absent_over_time" => {
+            arity(f, 1, Some(1))?;
+            let m = ev.eval_matrix(&f.args[0], ts, &ctx)?;
+            Value::Vector(if m.is_empty() {
+                vec![Sample {
+                    metric: absent_labels(&f.args[0]),
+                    t: ts,
+                    v: 1.0,
+                }]
+            } else {
+                Vec::new()
+            })
+        }
+        "absent" => {
+            arity(f, 1, Some(1))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            Value::Vector(if v.is_empty() {
+                vec![Sample {
+                    metric: absent_labels(&f.args[0]),
+                    t: ts,
+                    v: 1.0,
+                }]
+            } else {
+                Vec::new()
+            })
+        }
+        "label_replace" => {
+            arity(f, 5, Some(5))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let mut args = Vec::with_capacity(4);
+            for arg in &f.args[1..] {
+                args.push(ev.eval_string(arg, ts, &ctx)?);
+            }
+            Value::Vector(label_replace(v, &args[0], &args[1], &args[2], &args[3])?)
+        }
+        "label_join" => {
+            arity(f, 3, None)?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let mut args = Vec::with_capacity(f.args.len() - 1);
+            for arg in &f.args[1..] {
+                args.push(ev.eval_string(arg, ts, &ctx)?);
+            }
+            Value::Vector(label_join(v, &args[0], &args[1], &args[2..])?)
+        }
+        "sort" | "sort_desc" => {
+            arity(f, 1, Some(1))?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            Value::Vector(sort(v, name == "sort_desc"))
+        }
+        "sort_by_label" | "sort_by_label_desc" => {
+            // Unlike other functions, keeps the metric name.
+            arity(f, 1, None)?;
+            let v = ev.eval_vector(&f.args[0], ts, &ctx)?;
+            let mut labels = Vec::with_capacity(f.args.len() - 1);
+            for arg in &f.args[1..] {
+                labels.push(ev.eval_string(arg, ts, &ctx)?);
+            }
+            Value::Vector(sort_by_label(v, &labels, name == "sort_by_label_desc"))
+        }
+        _ => return Err(EngineError::UnknownFunction(f.name.clone())),
+    };
+
+    if let Value::Vector(v) = &out {
+        check_unique(v)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{query, storage, MIN};
+    use crate::engine::Engine;
+    use crate::parse_expr;
+    use crate::value::Value;
+
+    #[test]
+    fn test_math_functions() {
+        let s = storage(&[
+            (r#"x{a="1"}"#, &[-2.5]),
+            (r#"x{a="2"}"#, &[16.0]),
+            (r#"n{a="3"}"#, &[f64::NAN]),
+        ]);
+
+        assert_eq!(
+            query(&s, "abs(x)", 0).unwrap(),
+            vec![r#"{a="1"} 2.5"#, r#"{a="2"} 16"#]
+        );
+        assert_eq!(
+            query(&s, "clamp(x, 0, 10)", 0).unwrap(),
+            vec![r#"{a="1"} 0"#, r#"{a="2"} 10"#]
+        );
+        // NaN operands give NaN, as Go's `math.Max` and `math.Min` do.
+        assert_eq!(
+            query(&s, "clamp(n, 0, 10)", 0).unwrap(),
+            vec![r#"{a="3"} NaN"#]
+        );
+        assert_eq!(
+            query(&s, "clamp_min(x, NaN)", 0).unwrap(),
+            vec![r#"{a="1"} NaN"#, r#"{a="2"} NaN"#]
+        );
+        assert_eq!(
+            query(&s, "clamp_max(x, NaN)", 0).unwrap(),
+            vec![r#"{a="1"} NaN"#, r#"{a="2"} NaN"#]
+        );
+        assert_eq!(
+            query(&s, "clamp_max(n, -Inf)", 0).unwrap(),
+            vec![r#"{a="3"} -inf"#]
+        );
+        assert_eq!(
+            query(&s, "round(x, 5)", 0).unwrap(),
+            vec![r#"{a="1"} 0"#, r#"{a="2"} 15"#]
+        );
+        assert_eq!(query(&s, r#"scalar(x{a="2"})"#, 0).unwrap(), vec!["16"]);
+        assert_eq!(query(&s, "scalar(x)", 0).unwrap(), vec!["NaN"]);
+        assert_eq!(query(&s, "time()", 90_000).unwrap(), vec!["90"]);
+        assert_eq!(
+            query(&s, r#"timestamp(x{a="1"})"#, 2 * MIN).unwrap(),
+            vec![r#"{a="1"} 0"#]
+        );
+        assert_eq!(
+            query(&s, "abs(x, x)", 0).unwrap_err().to_string(),
+            r#"expected 1 argument(s) in call to "abs", got 2"#
+        );
+        assert_eq!(
+            query(&s, "abs(1)", 0).unwrap_err().to_string(),
+            r#"expected type instant vector in call to function "abs", got scalar"#
+        );
+        assert!(query(&s, "nope(x)", 0).is_err());
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let s = storage(&[]);
+        // 2024-02-29T13:45:00Z, a Thursday.
+        let t = 1_709_214_300_000;
+        let date = |f: &str| query(&s, &format!("{}()", f), t).unwrap();
+
+        assert_eq!(date("year"), vec!["{} 2024"]);
+        assert_eq!(date("month"), vec!["{} 2"]);
+        assert_eq!(date("day_of_month"), vec!["{} 29"]);
+        assert_eq!(date("day_of_week"), vec!["{} 4"]);
+        assert_eq!(date("day_of_year"), vec!["{} 60"]);
+        assert_eq!(date("days_in_month"), vec!["{} 29"]);
+        assert_eq!(date("hour"), vec!["{} 13"]);
+        assert_eq!(date("minute"), vec!["{} 45"]);
+    }
+
+    #[test]
+    fn test_label_functions() {
+        let s = storage(&[
+            (r#"up{job="api", instance="host-1:9090"}"#, &[1.0]),
+            (r#"up{job="web", instance="host-2:9090"}"#, &[1.0]),
+        ]);
+
+        assert_eq!(
+            query(
+                &s,
+                r#"label_replace(up, "host", "$1", "instance", "(.*):\\d+")"#,
+                0
+            )
+            .unwrap(),
+            vec![
+                r#"{__name__="up", host="host-1", instance="host-1:9090", job="api"} 1"#,
+                r#"{__name__="up", host="host-2", instance="host-2:9090", job="web"} 1"#,
+            ]
+        );
+        assert_eq!(
+            query(
+                &s,
+                r#"label_join(up{job="api"}, "id", "/", "job", "instance")"#,
+                0
+            )
+            .unwrap(),
+            vec![r#"{__name__="up", id="api/host-1:9090", instance="host-1:9090", job="api"} 1"#]
+        );
+        assert_eq!(
+            query(
+                &s,
+                r#"label_replace(label_replace(up, "job", "", "", ""), "instance", "", "", "")"#,
+                0
+            )
+            .unwrap_err()
+            .to_string(),
+            "vector cannot contain metrics with the same labelset"
+        );
+        assert_eq!(
+            query(&s, r#"absent(up{job="db", env="prod"})"#, 0).unwrap(),
+            vec![r#"{env="prod", job="db"} 1"#]
+        );
+        assert!(query(&s, "absent(up)", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_trend_functions() {
+        let s = storage(&[
+            (r#"x{job="a"}"#, &[1.0, 2.0, 2.0, 4.0, 4.0, 4.0, 7.0]),
+            (r#"y{job="a"}"#, &[0.0, 60.0, 120.0, 180.0]),
+            (r#"z{job="a"}"#, &[3.0, 3.0, 3.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "changes(x[10m])", 6 * MIN).unwrap(),
+            vec![r#"{job="a"} 3"#]
+        );
+        assert_eq!(
+            query(&s, "deriv(y[5m])", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 1"#]
+        );
+        assert_eq!(
+            query(&s, "deriv(z[5m])", 2 * MIN).unwrap(),
+            vec![r#"{job="a"} 0"#]
+        );
+        assert_eq!(
+            query(&s, "predict_linear(y[5m], 60)", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 240"#]
+        );
+        assert_eq!(
+            query(&s, "holt_winters(y[5m], 0.5, 0.5)", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 180"#]
+        );
+        assert_eq!(
+            query(
+                &s,
+                "double_exponential_smoothing(x[10m], 0.5, 0.5)",
+                2 * MIN
+            )
+            .unwrap(),
+            vec![r#"{job="a"} 2.5"#]
+        );
+        assert_eq!(
+            query(&s, "holt_winters(y[5m], 1, 0.5)", 3 * MIN)
+                .unwrap_err()
+                .to_string(),
+            "invalid smoothing factor. Expected: 0 < sf < 1, got: 1"
+        );
+        assert!(query(&s, "deriv(y[1m])", 3 * MIN).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sort_by_label() {
+        let s = storage(&[
+            (r#"up{instance="a10", job="x"}"#, &[1.0]),
+            (r#"up{instance="a2", job="y"}"#, &[1.0]),
+            (r#"up{instance="a2", job="x"}"#, &[1.0]),
+            (r#"up{job="x"}"#, &[1.0]),
+        ]);
+        let sorted = |q: &str| {
+            let (_, expr) = parse_expr(q).unwrap();
+            match Engine::new().instant_query(&s, &expr, 0).unwrap() {
+                Value::Vector(v) => v.iter().map(|s| s.metric.to_string()).collect::<Vec<_>>(),
+                v => panic!("not a vector: {:?}", v),
+            }
+        };
+
+        assert_eq!(
+            sorted(r#"sort_by_label(up, "instance")"#),
+            vec![
+                r#"{__name__="up", job="x"}"#,
+                r#"{__name__="up", instance="a2", job="x"}"#,
+                r#"{__name__="up", instance="a2", job="y"}"#,
+                r#"{__name__="up", instance="a10", job="x"}"#,
+            ]
+        );
+        assert_eq!(
+            sorted(r#"sort_by_label_desc(up, "job", "instance")"#),
+            vec![
+                r#"{__name__="up", instance="a2", job="y"}"#,
+                r#"{__name__="up", instance="a10", job="x"}"#,
+                r#"{__name__="up", instance="a2", job="x"}"#,
+                r#"{__name__="up", job="x"}"#,
+            ]
+        );
+    }
+}
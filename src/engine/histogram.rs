@@ -0,0 +1,146 @@
+use crate::value::{self, Labels, Sample};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    upper_bound: f64,
+    count: f64,
+}
+
+/// The `q`-quantile of classic histogram buckets, following Prometheus:
+/// buckets are sorted, merged by upper bound and made monotonic, and the
+/// quantile is interpolated linearly within the bucket holding its rank. NaN
+/// without a `+Inf` bucket or observations.
+fn bucket_quantile(q: f64, buckets: &mut Vec<Bucket>) -> f64 {
+    if q.is_nan() {
+        return f64::NAN;
+    }
+    if q < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return f64::INFINITY;
+    }
+    buckets.sort_by(|a, b| a.upper_bound.partial_cmp(&b.upper_bound).unwrap());
+    if buckets.last().map(|b| b.upper_bound) != Some(f64::INFINITY) {
+        return f64::NAN;
+    }
+    buckets.dedup_by(|b, a| {
+        if a.upper_bound == b.upper_bound {
+            a.count += b.count;
+            true
+        } else {
+            false
+        }
+    });
+    // Counts are scraped at slightly different times, so a bucket may lag
+    // behind a lower one.
+    let mut max = f64::NEG_INFINITY;
+    for b in buckets.iter_mut() {
+        if b.count > max {
+            max = b.count;
+        } else if b.count < max {
+            b.count = max;
+        }
+    }
+    if buckets.len() < 2 {
+        return f64::NAN;
+    }
+    let observations = buckets[buckets.len() - 1].count;
+    if observations == 0.0 {
+        return f64::NAN;
+    }
+
+    let mut rank = q * observations;
+    let b = buckets[..buckets.len() - 1].partition_point(|b| b.count < rank);
+    if b == buckets.len() - 1 {
+        return buckets[buckets.len() - 2].upper_bound;
+    }
+    if b == 0 && buckets[0].upper_bound <= 0.0 {
+        return buckets[0].upper_bound;
+    }
+    let (mut start, end, mut count) = (0.0, buckets[b].upper_bound, buckets[b].count);
+    if b > 0 {
+        start = buckets[b - 1].upper_bound;
+        count -= buckets[b - 1].count;
+        rank -= buckets[b - 1].count;
+    }
+    start + (end - start) * (rank / count)
+}
+
+/// `histogram_quantile` over classic histograms: series that only differ in
+/// `le` are the buckets of one histogram. Samples without a valid `le`, or
+/// with a NaN one, are ignored.
+pub(super) fn histogram_quantile(q: f64, v: value::Vector, ts: i64) -> value::Vector {
+    let mut index = HashMap::new();
+    let mut histograms: Vec<(Labels, Vec<Bucket>)> = Vec::new();
+    for s in v {
+        let upper_bound = match s.metric.get("le").and_then(|le| le.trim().parse().ok()) {
+            Some(le) if !f64::is_nan(le) => le,
+            _ => continue,
+        };
+        let i = *index
+            .entry(s.metric.hash_without_labels(&["le"]))
+            .or_insert_with(|| {
+                histograms.push((
+                    s.metric.drop_labels(&["le"]).without_metric_name(),
+                    Vec::new(),
+                ));
+                histograms.len() - 1
+            });
+        histograms[i].1.push(Bucket {
+            upper_bound,
+            count: s.v,
+        });
+    }
+
+    histograms
+        .into_iter()
+        .map(|(metric, mut buckets)| Sample {
+            metric,
+            t: ts,
+            v: bucket_quantile(q, &mut buckets),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{query, storage};
+
+    #[test]
+    fn test_histogram_quantile() {
+        let s = storage(&[
+            (r#"h_bucket{job="a", le="0.1"}"#, &[10.0]),
+            (r#"h_bucket{job="a", le="0.5"}"#, &[30.0]),
+            (r#"h_bucket{job="a", le="1"}"#, &[40.0]),
+            (r#"h_bucket{job="a", le="+Inf"}"#, &[50.0]),
+            (r#"h_bucket{job="b", le="1"}"#, &[5.0]),
+            (r#"h_bucket{job="b", le="+Inf"}"#, &[3.0]),
+            (r#"h_bucket{job="c", le="1"}"#, &[5.0]),
+        ]);
+        let quantile = |q: &str| query(&s, &format!("histogram_quantile({}, h_bucket)", q), 0);
+
+        // The rank of 25 lies 3/4 into the (0.1, 0.5] bucket. The +Inf bucket
+        // of `b` is raised to 5, and `c` has no +Inf bucket.
+        assert_eq!(
+            quantile("0.5").unwrap(),
+            vec![r#"{job="a"} 0.4"#, r#"{job="b"} 0.5"#, r#"{job="c"} NaN"#]
+        );
+        // Ranks in the +Inf bucket give the highest finite bound.
+        assert_eq!(quantile("0.9").unwrap()[0], r#"{job="a"} 1"#);
+        assert_eq!(quantile("0.2").unwrap()[0], r#"{job="a"} 0.1"#);
+        assert_eq!(quantile("2").unwrap()[0], r#"{job="a"} inf"#);
+        assert_eq!(quantile("-1").unwrap()[0], r#"{job="a"} -inf"#);
+
+        let s = storage(&[
+            (r#"h{le="NaN"}"#, &[1.0]),
+            (r#"h{le="1"}"#, &[2.0]),
+            (r#"h{le="+Inf"}"#, &[4.0]),
+        ]);
+        assert_eq!(
+            query(&s, "histogram_quantile(0.5, h)", 0).unwrap(),
+            vec!["{} 1"]
+        );
+    }
+}
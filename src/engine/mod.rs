@@ -0,0 +1,704 @@
+//! Evaluation of expressions against a [`Queryable`] storage.
+//!
+//! Follows Prometheus 3: range selectors and the lookback window of instant
+//! selectors are left-open, so a sample exactly `range` before the evaluation
+//! time is not selected. All timestamps are milliseconds since the Unix epoch.
+use crate::ast::{self, Expr, LabelMatcher, SubqueryExpr};
+use crate::matchers::selector_matchers;
+use crate::storage::{ListSeries, Querier, Queryable};
+use crate::timerange::{find_min_max_time, EvalRange};
+use crate::value::{self, is_stale_nan, Labels, Point, Sample, Scalar, Series, StringValue, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+
+mod aggregation;
+mod binary;
+mod error;
+mod functions;
+mod histogram;
+mod over_time;
+mod rate;
+
+pub use error::*;
+
+pub(crate) fn millis(d: Duration) -> i64 {
+    d.as_millis() as i64
+}
+
+/// Evaluates queries. Holds only settings, so one engine can serve any number
+/// of storages and concurrent queries.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Engine {
+    /// How far back an instant selector looks for the latest sample.
+    pub lookback_delta: Duration,
+    /// Resolution of subqueries that do not set one, like `x[1h:]`.
+    pub default_resolution: Duration,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            lookback_delta: Duration::from_secs(5 * 60),
+            default_resolution: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lookback_delta(mut self, lookback_delta: Duration) -> Self {
+        self.lookback_delta = lookback_delta;
+        self
+    }
+
+    pub fn default_resolution(mut self, default_resolution: Duration) -> Self {
+        self.default_resolution = default_resolution;
+        self
+    }
+
+    /// Evaluates `expr` at `time`.
+    pub fn instant_query<Q: Queryable + ?Sized>(
+        &self,
+        storage: &Q,
+        expr: &Expr,
+        time: i64,
+    ) -> Result<Value, EngineError> {
+        let range = EvalRange {
+            lookback_delta: self.lookback_delta,
+            ..EvalRange::instant(time)
+        };
+        Evaluator::new(self, storage, expr, range)?.eval(expr, time)
+    }
+
+    /// Evaluates `expr` at every `step` from `start` to `end`, giving one series
+    /// per label set seen at any step. Selectors are fetched once for the whole
+    /// range, and subtrees that do not depend on the step are evaluated once.
+    pub fn range_query<Q: Queryable + ?Sized>(
+        &self,
+        storage: &Q,
+        expr: &Expr,
+        start: i64,
+        end: i64,
+        step: Duration,
+    ) -> Result<value::Matrix, EngineError> {
+        if millis(step) <= 0 {
+            return Err(EngineError::InvalidArgument(
+                "zero or negative query resolution step widths are not accepted".to_owned(),
+            ));
+        }
+        if end < start {
+            return Err(EngineError::InvalidArgument(
+                "end timestamp must not be before start time".to_owned(),
+            ));
+        }
+
+        let range = EvalRange {
+            lookback_delta: self.lookback_delta,
+            ..EvalRange::range(start, end, step)
+        };
+        let mut ev = Evaluator::new(self, storage, expr, range)?;
+        let mut out = MatrixBuilder::default();
+        for t in (start..=end).step_by(millis(step) as usize) {
+            match ev.eval(expr, t)? {
+                Value::Scalar(s) => out.push(
+                    vec![Sample {
+                        metric: Labels::default(),
+                        t,
+                        v: s.v,
+                    }],
+                    t,
+                ),
+                Value::Vector(v) => out.push(v, t),
+                v => {
+                    return Err(EngineError::InvalidArgument(format!(
+                        "invalid expression type {:?} for range query, must be Scalar or instant Vector",
+                        type_name(&v)
+                    )))
+                }
+            }
+        }
+        Ok(out.finish())
+    }
+}
+
+/// Collects the instant vectors of successive steps into series.
+#[derive(Default)]
+struct MatrixBuilder {
+    index: HashMap<Labels, usize>,
+    series: Vec<Series>,
+}
+
+impl MatrixBuilder {
+    fn push(&mut self, v: value::Vector, t: i64) {
+        for s in v {
+            let i = match self.index.get(&s.metric) {
+                Some(&i) => i,
+                None => {
+                    self.index.insert(s.metric.clone(), self.series.len());
+                    self.series.push(Series {
+                        metric: s.metric,
+                        points: Vec::new(),
+                    });
+                    self.series.len() - 1
+                }
+            };
+            self.series[i].points.push(Point { t, v: s.v });
+        }
+    }
+
+    fn finish(mut self) -> value::Matrix {
+        self.series.sort_by(|a, b| a.metric.cmp(&b.metric));
+        self.series
+    }
+}
+
+/// Functions whose result depends on the evaluation time even when their
+/// arguments do not.
+const TIME_DEPENDENT_FUNCTIONS: &[&str] = &[
+    "time",
+    "timestamp",
+    "year",
+    "month",
+    "day_of_month",
+    "day_of_week",
+    "day_of_year",
+    "days_in_month",
+    "hour",
+    "minute",
+    "predict_linear",
+];
+
+/// Adds to `out` the address of every node of `expr` whose value is the same
+/// at every step, apart from literals which are cheap anyway, and returns
+/// whether `expr` itself is such a node. Only literals and `@`-pinned
+/// selectors are step-invariant at the leaves.
+fn mark_step_invariant(expr: &Expr, out: &mut HashSet<usize>) -> bool {
+    let children = expr.children().fold(true, |all, (_, child)| {
+        mark_step_invariant(child, out) && all
+    });
+    let invariant = match expr {
+        Expr::NumberLiteralExpr(_) | Expr::StringLiteralExpr(_) => return true,
+        Expr::VectorExpr(v) => v.at.is_some(),
+        Expr::SubQueryExpr(_) => false,
+        Expr::FunCallExpr(f) if TIME_DEPENDENT_FUNCTIONS.contains(&f.name.as_str()) => false,
+        _ => children,
+    };
+    if invariant {
+        out.insert(expr as *const Expr as usize);
+    }
+    invariant
+}
+
+/// `v` as evaluated at `ts` rather than at the step it was computed for.
+fn at_time(v: Value, ts: i64) -> Value {
+    match v {
+        Value::Scalar(s) => Value::Scalar(Scalar { t: ts, v: s.v }),
+        Value::String(s) => Value::String(StringValue { t: ts, v: s.v }),
+        Value::Vector(v) => Value::Vector(
+            v.into_iter()
+                .map(|s| Sample {
+                    metric: s.metric,
+                    t: ts,
+                    v: s.v,
+                })
+                .collect(),
+        ),
+        Value::Matrix(m) => Value::Matrix(m),
+    }
+}
+
+/// Points of `points` within `(mint, maxt]`.
+fn window(points: &[Point], mint: i64, maxt: i64) -> &[Point] {
+    let start = points.partition_point(|p| p.t <= mint);
+    let end = points.partition_point(|p| p.t <= maxt);
+    &points[start..end.max(start)]
+}
+
+/// Fails if two samples of `v` have the same labels, which happens when
+/// dropping the metric name merges series.
+pub(crate) fn check_unique(v: &[Sample]) -> Result<(), EngineError> {
+    let mut seen = HashSet::with_capacity(v.len());
+    if v.iter().all(|s| seen.insert(&s.metric)) {
+        Ok(())
+    } else {
+        Err(EngineError::DuplicateLabelSet)
+    }
+}
+
+pub(crate) struct Evaluator<'a> {
+    querier: Box<dyn Querier + 'a>,
+    range: EvalRange,
+    default_resolution: i64,
+    /// Series of every distinct selector, fetched once for the whole query.
+    series: HashMap<Vec<LabelMatcher>, Rc<Vec<ListSeries>>>,
+    /// Addresses of the step-invariant nodes of the query, which stays borrowed
+    /// for as long as the evaluator lives.
+    invariant: HashSet<usize>,
+    /// Values of step-invariant nodes, computed at the first step.
+    hoisted: HashMap<usize, Value>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new<Q: Queryable + ?Sized>(
+        engine: &Engine,
+        storage: &'a Q,
+        expr: &Expr,
+        range: EvalRange,
+    ) -> Result<Self, EngineError> {
+        let (mint, maxt) = find_min_max_time(expr, &range).unwrap_or((range.start, range.end));
+        let mut invariant = HashSet::new();
+        mark_step_invariant(expr, &mut invariant);
+        Ok(Self {
+            querier: storage.querier(mint, maxt)?,
+            range,
+            default_resolution: millis(engine.default_resolution),
+            series: HashMap::new(),
+            invariant,
+            hoisted: HashMap::new(),
+        })
+    }
+
+    fn select(&mut self, v: &ast::Vector) -> Result<Rc<Vec<ListSeries>>, EngineError> {
+        let matchers = selector_matchers(v);
+        if let Some(series) = self.series.get(&matchers) {
+            return Ok(series.clone());
+        }
+
+        let series: Vec<ListSeries> = self
+            .querier
+            .select(true, None, &matchers)?
+            .map(|s| ListSeries::new(s.labels().clone(), s.samples().collect()))
+            .collect();
+        let series = Rc::new(series);
+        self.series.insert(matchers, series.clone());
+        Ok(series)
+    }
+
+    /// The time a selector reads at when evaluated at `ts`.
+    fn selector_time(&self, v: &ast::Vector, ts: i64) -> i64 {
+        let t = match &v.at {
+            Some(at) => self.range.resolve_at(at),
+            None => ts,
+        };
+        t - millis(v.offset.unwrap_or_default())
+    }
+
+    /// Latest sample of each series within the lookback window. Samples carry
+    /// `ts` as their time unless `sample_time` asks for their own timestamps.
+    pub(crate) fn instant_selector(
+        &mut self,
+        v: &ast::Vector,
+        ts: i64,
+        sample_time: bool,
+    ) -> Result<value::Vector, EngineError> {
+        let t = self.selector_time(v, ts);
+        let lookback = millis(self.range.lookback_delta);
+        let series = self.select(v)?;
+
+        Ok(series
+            .iter()
+            .filter_map(|s| {
+                let p = window(&s.points, t - lookback, t).last()?;
+                if is_stale_nan(p.v) {
+                    return None;
+                }
+                Some(Sample {
+                    metric: s.labels.clone(),
+                    t: if sample_time { p.t } else { ts },
+                    v: p.v,
+                })
+            })
+            .collect())
+    }
+
+    fn range_selector(&mut self, v: &ast::Vector, ts: i64) -> Result<value::Matrix, EngineError> {
+        let t = self.selector_time(v, ts);
+        let range = millis(v.range.unwrap_or_default());
+        let series = self.select(v)?;
+
+        Ok(series
+            .iter()
+            .filter_map(|s| {
+                let points: Vec<Point> = window(&s.points, t - range, t)
+                    .iter()
+                    .filter(|p| !is_stale_nan(p.v))
+                    .copied()
+                    .collect();
+                if points.is_empty() {
+                    return None;
+                }
+                Some(Series {
+                    metric: s.labels.clone(),
+                    points,
+                })
+            })
+            .collect())
+    }
+
+    /// Evaluates the subquery at `ts`. Its steps are aligned to absolute
+    /// multiples of the resolution rather than to `ts`, so they do not shift
+    /// between evaluations; the range is left-open like a range selector's.
+    fn subquery(&mut self, s: &SubqueryExpr, ts: i64) -> Result<value::Matrix, EngineError> {
+        let resolution = match s.resolution.map(millis) {
+            Some(r) if r > 0 => r,
+            _ => self.default_resolution,
+        };
+        let mint = ts - millis(s.range.unwrap_or_default());
+        let first = (mint.div_euclid(resolution) + 1) * resolution;
+
+        let mut out = MatrixBuilder::default();
+        for t in (first..=ts).step_by(resolution as usize) {
+            let v = self.eval_vector(&s.expr, t, "subquery")?;
+            out.push(v, t);
+        }
+        Ok(out.finish())
+    }
+
+    pub(crate) fn eval(&mut self, expr: &Expr, ts: i64) -> Result<Value, EngineError> {
+        let key = expr as *const Expr as usize;
+        if !self.invariant.contains(&key) {
+            return self.eval_step(expr, ts);
+        }
+        if let Some(v) = self.hoisted.get(&key) {
+            return Ok(at_time(v.clone(), ts));
+        }
+        let v = self.eval_step(expr, ts)?;
+        self.hoisted.insert(key, v.clone());
+        Ok(v)
+    }
+
+    fn eval_step(&mut self, expr: &Expr, ts: i64) -> Result<Value, EngineError> {
+        match expr {
+            Expr::NumberLiteralExpr(n) => Ok(Value::Scalar(Scalar { t: ts, v: n.value })),
+            Expr::StringLiteralExpr(s) => Ok(Value::String(StringValue {
+                t: ts,
+                v: s.unescaped(),
+            })),
+            Expr::VectorExpr(v) if v.range.is_some() => {
+                Ok(Value::Matrix(self.range_selector(v, ts)?))
+            }
+            Expr::VectorExpr(v) => Ok(Value::Vector(self.instant_selector(v, ts, false)?)),
+            Expr::NegationExpr(e) => match self.eval(e, ts)? {
+                Value::Scalar(s) => Ok(Value::Scalar(Scalar { t: s.t, v: -s.v })),
+                Value::Vector(v) => Ok(Value::Vector(
+                    v.into_iter()
+                        .map(|s| Sample {
+                            metric: s.metric.without_metric_name(),
+                            t: s.t,
+                            v: -s.v,
+                        })
+                        .collect(),
+                )),
+                v => Err(EngineError::TypeMismatch {
+                    context: "unary expression".to_owned(),
+                    expected: "scalar or instant vector",
+                    got: type_name(&v),
+                }),
+            },
+            Expr::BinaryExpr(b) => binary::eval(self, b, ts),
+            Expr::FunCallExpr(f) if f.is_aggregation() => aggregation::eval(self, f, ts),
+            Expr::FunCallExpr(f) => functions::eval(self, f, ts),
+            Expr::SubQueryExpr(s) => Ok(Value::Matrix(self.subquery(s, ts)?)),
+        }
+    }
+
+    pub(crate) fn eval_vector(
+        &mut self,
+        expr: &Expr,
+        ts: i64,
+        context: &str,
+    ) -> Result<value::Vector, EngineError> {
+        match self.eval(expr, ts)? {
+            Value::Vector(v) => Ok(v),
+            v => Err(mismatch(context, "instant vector", &v)),
+        }
+    }
+
+    pub(crate) fn eval_matrix(
+        &mut self,
+        expr: &Expr,
+        ts: i64,
+        context: &str,
+    ) -> Result<value::Matrix, EngineError> {
+        match self.eval(expr, ts)? {
+            Value::Matrix(m) => Ok(m),
+            v => Err(mismatch(context, "range vector", &v)),
+        }
+    }
+
+    /// Bounds `(start, end]` of the range that a range vector expression covers
+    /// when evaluated at `ts`.
+    pub(crate) fn matrix_range(&self, expr: &Expr, ts: i64) -> (i64, i64) {
+        match expr {
+            Expr::VectorExpr(v) => {
+                let t = self.selector_time(v, ts);
+                (t - millis(v.range.unwrap_or_default()), t)
+            }
+            Expr::SubQueryExpr(s) => (ts - millis(s.range.unwrap_or_default()), ts),
+            _ => (ts, ts),
+        }
+    }
+
+    pub(crate) fn eval_scalar(
+        &mut self,
+        expr: &Expr,
+        ts: i64,
+        context: &str,
+    ) -> Result<f64, EngineError> {
+        match self.eval(expr, ts)? {
+            Value::Scalar(s) => Ok(s.v),
+            v => Err(mismatch(context, "scalar", &v)),
+        }
+    }
+
+    pub(crate) fn eval_string(
+        &mut self,
+        expr: &Expr,
+        ts: i64,
+        context: &str,
+    ) -> Result<String, EngineError> {
+        match self.eval(expr, ts)? {
+            Value::String(s) => Ok(s.v),
+            v => Err(mismatch(context, "string", &v)),
+        }
+    }
+}
+
+fn mismatch(context: &str, expected: &'static str, got: &Value) -> EngineError {
+    EngineError::TypeMismatch {
+        context: context.to_owned(),
+        expected,
+        got: type_name(got),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parse_expr;
+    use crate::storage::MemoryStorage;
+
+    pub(crate) const MIN: i64 = 60 * 1000;
+
+    /// Labels of a selector written as `name{a="b", …}`.
+    pub(crate) fn labels(selector: &str) -> Labels {
+        match parse_expr(selector).unwrap().1 {
+            Expr::VectorExpr(v) => selector_matchers(&v)
+                .iter()
+                .map(|m| value::Label::new(&m.name, &m.value))
+                .collect(),
+            e => panic!("not a selector: {}", e),
+        }
+    }
+
+    /// Storage holding `series`, each with one sample per minute from 0.
+    pub(crate) fn storage(series: &[(&str, &[f64])]) -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        for (selector, values) in series {
+            let labels = labels(selector);
+            for (i, v) in values.iter().enumerate() {
+                storage.append(&labels, i as i64 * MIN, *v).unwrap();
+            }
+        }
+        storage
+    }
+
+    /// One line per series of `m`, as `{labels} v@t v@t …`.
+    fn matrix_lines(m: &[Series]) -> Vec<String> {
+        m.iter()
+            .map(|s| {
+                let points: Vec<String> = s
+                    .points
+                    .iter()
+                    .map(|p| format!("{}@{}", p.v, p.t))
+                    .collect();
+                format!("{} {}", s.metric, points.join(" "))
+            })
+            .collect()
+    }
+
+    /// Result of an instant query, one sorted line per element.
+    pub(crate) fn query(
+        storage: &MemoryStorage,
+        q: &str,
+        t: i64,
+    ) -> Result<Vec<String>, EngineError> {
+        let (rest, expr) = parse_expr(q).unwrap();
+        assert_eq!(rest, "", "{}", q);
+        let mut out: Vec<String> = match Engine::new().instant_query(storage, &expr, t)? {
+            Value::Vector(v) => v.iter().map(|s| format!("{} {}", s.metric, s.v)).collect(),
+            Value::Matrix(m) => matrix_lines(&m),
+            Value::Scalar(s) => vec![s.v.to_string()],
+            Value::String(s) => vec![s.v],
+        };
+        out.sort();
+        Ok(out)
+    }
+
+    /// Result of a range query from `start` to `end` by minute.
+    fn range_query(
+        storage: &MemoryStorage,
+        q: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<String>, EngineError> {
+        let expr = parse_expr(q).unwrap().1;
+        let m = Engine::new().range_query(storage, &expr, start, end, Duration::from_secs(60))?;
+        Ok(matrix_lines(&m))
+    }
+
+    #[test]
+    fn test_selectors() {
+        let s = storage(&[
+            (r#"up{job="a"}"#, &[1.0, 2.0, 3.0]),
+            (r#"up{job="b"}"#, &[4.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "up", 2 * MIN).unwrap(),
+            vec![
+                r#"{__name__="up", job="a"} 3"#,
+                r#"{__name__="up", job="b"} 4"#
+            ]
+        );
+        // The lookback window is left-open.
+        assert_eq!(
+            query(&s, "up", 5 * MIN).unwrap(),
+            vec![r#"{__name__="up", job="a"} 3"#]
+        );
+        assert_eq!(
+            query(&s, "up offset 1m", 2 * MIN).unwrap(),
+            vec![
+                r#"{__name__="up", job="a"} 2"#,
+                r#"{__name__="up", job="b"} 4"#
+            ]
+        );
+        assert_eq!(
+            query(&s, "up{job=\"a\"} @ 60", 10 * MIN).unwrap(),
+            vec![r#"{__name__="up", job="a"} 2"#]
+        );
+        assert_eq!(
+            query(&s, "up{job=\"a\"}[2m]", 2 * MIN).unwrap(),
+            vec![r#"{__name__="up", job="a"} 2@60000 3@120000"#]
+        );
+    }
+
+    #[test]
+    fn test_staleness() {
+        let s = storage(&[(
+            r#"up{job="a"}"#,
+            &[1.0, f64::from_bits(value::STALE_NAN_BITS), 3.0],
+        )]);
+        assert_eq!(query(&s, "up", 0).unwrap().len(), 1);
+        assert!(query(&s, "up", MIN + 30_000).unwrap().is_empty());
+        assert_eq!(
+            query(&s, "up[3m]", 2 * MIN).unwrap(),
+            vec![r#"{__name__="up", job="a"} 1@0 3@120000"#]
+        );
+    }
+
+    #[test]
+    fn test_literals_and_negation() {
+        let s = storage(&[(r#"up{job="a"}"#, &[1.0])]);
+        assert_eq!(query(&s, "-2.5", 0).unwrap(), vec!["-2.5"]);
+        assert_eq!(query(&s, "-up", 0).unwrap(), vec![r#"{job="a"} -1"#]);
+        assert_eq!(
+            query(&s, "-up[1m]", 0).unwrap_err().to_string(),
+            "expected type scalar or instant vector in unary expression, got range vector"
+        );
+    }
+
+    #[test]
+    fn test_range_query() {
+        let s = storage(&[
+            (r#"up{job="a"}"#, &[1.0, 2.0, 3.0]),
+            (r#"up{job="b"}"#, &[4.0, 5.0]),
+        ]);
+
+        assert_eq!(
+            range_query(&s, "up", 0, 8 * MIN).unwrap(),
+            vec![
+                r#"{__name__="up", job="a"} 1@0 2@60000 3@120000 3@180000 3@240000 3@300000 3@360000"#,
+                r#"{__name__="up", job="b"} 4@0 5@60000 5@120000 5@180000 5@240000 5@300000"#
+            ]
+        );
+        assert_eq!(
+            range_query(&s, "up{job=\"b\"} * 2 + 1", 0, MIN).unwrap(),
+            vec![r#"{job="b"} 9@0 11@60000"#]
+        );
+        assert_eq!(
+            range_query(&s, "1 + 1", 0, 2 * MIN).unwrap(),
+            vec!["{} 2@0 2@60000 2@120000"]
+        );
+        assert_eq!(
+            range_query(&s, "up[1m]", 0, MIN).unwrap_err().to_string(),
+            r#"invalid expression type "range vector" for range query, must be Scalar or instant Vector"#
+        );
+
+        let expr = parse_expr("up").unwrap().1;
+        let engine = Engine::new();
+        assert!(engine
+            .range_query(&s, &expr, MIN, 0, Duration::from_secs(60))
+            .is_err());
+        assert!(engine
+            .range_query(&s, &expr, 0, MIN, Duration::from_micros(10))
+            .is_err());
+    }
+
+    #[test]
+    fn test_step_invariant_hoisting() {
+        let s = storage(&[(r#"up{job="a"}"#, &[1.0, 2.0, 3.0])]);
+
+        let expr = parse_expr("scalar(sum(up @ 60)) * 2 + up - time()")
+            .unwrap()
+            .1;
+        let mut invariant = HashSet::new();
+        mark_step_invariant(&expr, &mut invariant);
+        // `up @ 60`, `sum(…)`, `scalar(…)` and `scalar(…) * 2`.
+        assert_eq!(invariant.len(), 4);
+
+        assert_eq!(
+            range_query(&s, "scalar(sum(up @ 60)) * 2 + up", 0, 2 * MIN).unwrap(),
+            vec![r#"{job="a"} 5@0 6@60000 7@120000"#]
+        );
+        assert_eq!(
+            range_query(&s, "timestamp(up @ 60)", 0, MIN).unwrap(),
+            vec![r#"{job="a"} 60@0 60@60000"#]
+        );
+        assert_eq!(
+            range_query(&s, "predict_linear(up[2m] @ 60, 60)", 0, MIN).unwrap(),
+            vec![r#"{job="a"} 2@0 3@60000"#]
+        );
+    }
+
+    #[test]
+    fn test_subquery_alignment() {
+        let s = storage(&[(r#"up{job="a"}"#, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0])]);
+
+        assert_eq!(
+            query(&s, "up[3m:1m]", 150_000).unwrap(),
+            vec![r#"{__name__="up", job="a"} 0@0 1@60000 2@120000"#]
+        );
+        // Steps stay on multiples of the resolution whatever the query time.
+        for t in &[5 * MIN, 5 * MIN + 30_000] {
+            assert_eq!(
+                query(&s, "up[3m:2m]", *t).unwrap(),
+                vec![r#"{__name__="up", job="a"} 4@240000"#]
+            );
+        }
+        assert_eq!(
+            query(&s, "(up * 2)[2m:1m]", 2 * MIN).unwrap(),
+            vec![r#"{job="a"} 2@60000 4@120000"#]
+        );
+        assert_eq!(
+            query(&s, "(1 + 1)[2m:1m]", 2 * MIN)
+                .unwrap_err()
+                .to_string(),
+            "expected type instant vector in subquery, got scalar"
+        );
+    }
+}
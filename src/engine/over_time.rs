@@ -0,0 +1,271 @@
+use crate::value::Point;
+use std::cmp::Ordering;
+
+/// Adds `inc` to `sum` with Neumaier's variant of Kahan summation, `c` being
+/// the running compensation. Gives the new sum and compensation.
+pub(super) fn kahan_sum_inc(inc: f64, sum: f64, c: f64) -> (f64, f64) {
+    let t = sum + inc;
+    let c = if t.is_infinite() {
+        0.0
+    } else if sum.abs() >= inc.abs() {
+        c + ((sum - t) + inc)
+    } else {
+        c + ((inc - t) + sum)
+    };
+    (t, c)
+}
+
+pub(super) fn kahan_sum<I: IntoIterator<Item = f64>>(values: I) -> f64 {
+    let (sum, c) = values
+        .into_iter()
+        .fold((0.0, 0.0), |(sum, c), v| kahan_sum_inc(v, sum, c));
+    if sum.is_infinite() {
+        sum
+    } else {
+        sum + c
+    }
+}
+
+/// Mean of `values`, switching to an incremental mean if the sum overflows.
+pub(super) fn mean<I: IntoIterator<Item = f64>>(values: I) -> f64 {
+    let (mut sum, mut mean, mut c, mut count) = (0.0, 0.0, 0.0, 0.0);
+    let mut incremental = false;
+    for v in values {
+        count += 1.0;
+        if !incremental {
+            let (new_sum, new_c) = kahan_sum_inc(v, sum, c);
+            if count == 1.0 || !new_sum.is_infinite() {
+                sum = new_sum;
+                c = new_c;
+                continue;
+            }
+            incremental = true;
+            mean = sum / (count - 1.0);
+            c /= count - 1.0;
+        }
+        if mean.is_infinite() {
+            // An infinite mean stays as it is unless `v` is an infinity of
+            // the other sign or NaN.
+            if v.is_infinite() && (mean > 0.0) == (v > 0.0) {
+                continue;
+            }
+            if v.is_finite() {
+                continue;
+            }
+        }
+        let corrected = mean + c;
+        let (m, new_c) = kahan_sum_inc(v / count - corrected / count, mean, c);
+        mean = m;
+        c = new_c;
+    }
+    if incremental {
+        mean + c
+    } else {
+        sum / count + c / count
+    }
+}
+
+/// Population variance of `values`, by Welford's algorithm.
+pub(super) fn variance<I: IntoIterator<Item = f64>>(values: I) -> f64 {
+    let (mut count, mut mean, mut c_mean, mut aux, mut c_aux) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for v in values {
+        count += 1.0;
+        let delta = v - (mean + c_mean);
+        let (m, c) = kahan_sum_inc(delta / count, mean, c_mean);
+        mean = m;
+        c_mean = c;
+        let (a, c) = kahan_sum_inc(delta * (v - (mean + c_mean)), aux, c_aux);
+        aux = a;
+        c_aux = c;
+    }
+    (aux + c_aux) / count
+}
+
+/// The `q`-quantile of `values`, interpolating between the two nearest ranks.
+/// NaN sorts first, and `q` outside `[0, 1]` gives an infinity.
+pub(super) fn quantile(q: f64, values: &mut [f64]) -> f64 {
+    if values.is_empty() || q.is_nan() {
+        return f64::NAN;
+    }
+    if q < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return f64::INFINITY;
+    }
+    values.sort_by(|a, b| match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => a.partial_cmp(b).unwrap(),
+    });
+
+    let n = values.len() as f64;
+    let rank = q * (n - 1.0);
+    let lower = rank.floor().max(0.0);
+    let upper = (lower + 1.0).min(n - 1.0);
+    let weight = rank - rank.floor();
+    values[lower as usize] * (1.0 - weight) + values[upper as usize] * weight
+}
+
+/// Least-squares fit of the points, with times in seconds relative to
+/// `intercept_time`. Gives the slope and the value at `intercept_time`.
+pub(super) fn linear_regression(points: &[Point], intercept_time: i64) -> (f64, f64) {
+    let first = points[0].v;
+    if points.iter().all(|p| p.v == first) {
+        // Avoids rounding errors for a constant series.
+        return if first.is_infinite() {
+            (f64::NAN, f64::NAN)
+        } else {
+            (0.0, first)
+        };
+    }
+
+    let (mut sum_x, mut c_x, mut sum_y, mut c_y) = (0.0, 0.0, 0.0, 0.0);
+    let (mut sum_xy, mut c_xy, mut sum_x2, mut c_x2) = (0.0, 0.0, 0.0, 0.0);
+    for p in points {
+        let x = (p.t - intercept_time) as f64 / 1000.0;
+        (sum_x, c_x) = kahan_sum_inc(x, sum_x, c_x);
+        (sum_y, c_y) = kahan_sum_inc(p.v, sum_y, c_y);
+        (sum_xy, c_xy) = kahan_sum_inc(x * p.v, sum_xy, c_xy);
+        (sum_x2, c_x2) = kahan_sum_inc(x * x, sum_x2, c_x2);
+    }
+    let n = points.len() as f64;
+    let (sum_x, sum_y, sum_xy, sum_x2) = (sum_x + c_x, sum_y + c_y, sum_xy + c_xy, sum_x2 + c_x2);
+
+    let cov_xy = sum_xy - sum_x * sum_y / n;
+    let var_x = sum_x2 - sum_x * sum_x / n;
+    let slope = cov_xy / var_x;
+    (slope, sum_y / n - slope * sum_x / n)
+}
+
+/// Number of times the value changes, a NaN following a NaN not counting.
+pub(super) fn changes(points: &[Point]) -> f64 {
+    points
+        .windows(2)
+        .filter(|w| w[1].v != w[0].v && !(w[0].v.is_nan() && w[1].v.is_nan()))
+        .count() as f64
+}
+
+/// Holt's linear trend method, with smoothing factor `sf` and trend factor
+/// `tf`, both in `(0, 1)`. `None` with fewer than two points.
+pub(super) fn double_exponential_smoothing(points: &[Point], sf: f64, tf: f64) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (mut s0, mut s1) = (0.0, points[0].v);
+    let mut trend = points[1].v - points[0].v;
+    for (i, p) in points.iter().enumerate().skip(1) {
+        if i > 1 {
+            trend = tf * (s1 - s0) + (1.0 - tf) * trend;
+        }
+        let smoothed = sf * p.v + (1.0 - sf) * (s1 + trend);
+        s0 = s1;
+        s1 = smoothed;
+    }
+    Some(s1)
+}
+
+/// The point `better` prefers over all earlier ones. A NaN is replaced by any
+/// later point.
+fn compare_over_time(points: &[Point], better: fn(f64, f64) -> bool) -> Point {
+    points.iter().fold(points[0], |acc, &p| {
+        if better(p.v, acc.v) || acc.v.is_nan() {
+            p
+        } else {
+            acc
+        }
+    })
+}
+
+fn values(points: &[Point]) -> impl Iterator<Item = f64> + '_ {
+    points.iter().map(|p| p.v)
+}
+
+fn mad(points: &[Point]) -> f64 {
+    let mut v: Vec<f64> = values(points).collect();
+    let median = quantile(0.5, &mut v);
+    let mut deviations: Vec<f64> = values(points).map(|v| (v - median).abs()).collect();
+    quantile(0.5, &mut deviations)
+}
+
+/// The `*_over_time` function called `name` that reduces the points of a
+/// series to one value, other than those taking extra arguments.
+pub(super) fn over_time_function(name: &str) -> Option<fn(&[Point]) -> f64> {
+    Some(match name {
+        "avg_over_time" => |p| mean(values(p)),
+        "sum_over_time" => |p| kahan_sum(values(p)),
+        "min_over_time" => |p| compare_over_time(p, |v, min| v < min).v,
+        "max_over_time" => |p| compare_over_time(p, |v, max| v > max).v,
+        "count_over_time" => |p| p.len() as f64,
+        "present_over_time" => |_| 1.0,
+        "stddev_over_time" => |p| variance(values(p)).sqrt(),
+        "stdvar_over_time" => |p| variance(values(p)),
+        "mad_over_time" => mad,
+        // The latest of equal extremes wins.
+        "ts_of_min_over_time" => |p| compare_over_time(p, |v, min| v <= min).t as f64 / 1000.0,
+        "ts_of_max_over_time" => |p| compare_over_time(p, |v, max| v >= max).t as f64 / 1000.0,
+        "ts_of_last_over_time" => |p| p[p.len() - 1].t as f64 / 1000.0,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{query, storage, MIN};
+
+    #[test]
+    fn test_compensated_sums() {
+        assert_eq!(kahan_sum(vec![1e100, 1.0, -1e100]), 1.0);
+        assert_eq!(mean(vec![f64::MAX, f64::MAX, f64::MAX]), f64::MAX);
+        assert_eq!(mean(vec![f64::INFINITY, 1.0]), f64::INFINITY);
+        assert!(mean(vec![f64::INFINITY, f64::NEG_INFINITY]).is_nan());
+        assert_eq!(quantile(2.0, &mut [1.0]), f64::INFINITY);
+        assert_eq!(quantile(0.5, &mut [3.0, f64::NAN, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn test_over_time_functions() {
+        let s = storage(&[
+            (r#"x{job="a"}"#, &[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]),
+            (r#"y{job="a"}"#, &[1.0, 3.0, 3.0, 2.0]),
+        ]);
+        let t = 7 * MIN;
+        let over_time = |f: &str| query(&s, &format!("{}(x[8m])", f), t).unwrap();
+
+        assert_eq!(over_time("sum_over_time"), vec![r#"{job="a"} 40"#]);
+        assert_eq!(over_time("avg_over_time"), vec![r#"{job="a"} 5"#]);
+        assert_eq!(over_time("min_over_time"), vec![r#"{job="a"} 2"#]);
+        assert_eq!(over_time("max_over_time"), vec![r#"{job="a"} 9"#]);
+        assert_eq!(over_time("count_over_time"), vec![r#"{job="a"} 8"#]);
+        assert_eq!(over_time("present_over_time"), vec![r#"{job="a"} 1"#]);
+        assert_eq!(over_time("stddev_over_time"), vec![r#"{job="a"} 2"#]);
+        assert_eq!(over_time("stdvar_over_time"), vec![r#"{job="a"} 4"#]);
+        assert_eq!(over_time("mad_over_time"), vec![r#"{job="a"} 0.5"#]);
+        assert_eq!(over_time("ts_of_min_over_time"), vec![r#"{job="a"} 0"#]);
+        assert_eq!(over_time("ts_of_last_over_time"), vec![r#"{job="a"} 420"#]);
+        assert_eq!(
+            over_time("last_over_time"),
+            vec![r#"{__name__="x", job="a"} 9"#]
+        );
+        assert_eq!(
+            query(&s, "quantile_over_time(0.25, x[8m])", t).unwrap(),
+            vec![r#"{job="a"} 4"#]
+        );
+        assert_eq!(
+            query(&s, "ts_of_max_over_time(y[8m])", t).unwrap(),
+            vec![r#"{job="a"} 120"#]
+        );
+        assert_eq!(
+            query(&s, "max_over_time((x * 2)[4m:2m])", t).unwrap(),
+            vec![r#"{job="a"} 14"#]
+        );
+
+        assert_eq!(
+            query(&s, r#"absent_over_time(x{job="b"}[5m])"#, t).unwrap(),
+            vec![r#"{job="b"} 1"#]
+        );
+        assert!(query(&s, "absent_over_time(x[5m])", t).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::value::Point;
+
+/// `rate`, `increase` and `delta` of the samples of a range `(start, end]`, in
+/// milliseconds, following Prometheus: the change between the first and last
+/// sample, corrected for counter resets, extrapolated towards the range
+/// boundaries. `None` with fewer than two samples.
+pub(super) fn extrapolated_rate(
+    points: &[Point],
+    start: i64,
+    end: i64,
+    is_counter: bool,
+    is_rate: bool,
+) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+
+    let mut result = last.v - first.v;
+    if is_counter {
+        let mut prev = first.v;
+        for p in &points[1..] {
+            if p.v < prev {
+                result += prev;
+            }
+            prev = p.v;
+        }
+    }
+
+    let mut to_start = (first.t - start) as f64 / 1000.0;
+    let mut to_end = (end - last.t) as f64 / 1000.0;
+    let sampled = (last.t - first.t) as f64 / 1000.0;
+    let average = sampled / (points.len() - 1) as f64;
+
+    // Extrapolate all the way to a boundary only if a sample lies within 110%
+    // of the average interval from it; otherwise assume the series starts or
+    // ends inside the range, half an interval beyond its first or last sample.
+    let threshold = average * 1.1;
+    if to_start >= threshold {
+        to_start = average / 2.0;
+    }
+    if is_counter && result > 0.0 && first.v >= 0.0 {
+        // Counters cannot go negative: stop where the slope reaches zero.
+        let to_zero = sampled * (first.v / result);
+        if to_zero < to_start {
+            to_start = to_zero;
+        }
+    }
+    if to_end >= threshold {
+        to_end = average / 2.0;
+    }
+
+    let mut factor = (sampled + to_start + to_end) / sampled;
+    if is_rate {
+        factor /= (end - start) as f64 / 1000.0;
+    }
+    Some(result * factor)
+}
+
+/// `irate` and `idelta`: the change between the last two samples, per second
+/// for `irate`, which treats a decrease as a counter reset.
+pub(super) fn instant_value(points: &[Point], is_rate: bool) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (prev, last) = (points[points.len() - 2], points[points.len() - 1]);
+
+    let result = if is_rate && last.v < prev.v {
+        last.v
+    } else {
+        last.v - prev.v
+    };
+    let interval = last.t - prev.t;
+    if interval == 0 {
+        return None;
+    }
+    Some(if is_rate {
+        result / (interval as f64 / 1000.0)
+    } else {
+        result
+    })
+}
+
+/// Number of times the value decreases.
+pub(super) fn resets(points: &[Point]) -> f64 {
+    points.windows(2).filter(|w| w[1].v < w[0].v).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::tests::{labels, query, storage, MIN};
+    use crate::engine::Engine;
+    use crate::parse_expr;
+    use crate::storage::MemoryStorage;
+    use crate::value::Value;
+
+    /// Storage holding `series` sampled every `interval`, with values written
+    /// as in Prometheus' test scripts: `a+bxn` expands to `a, a+b, …, a+n*b`,
+    /// and `_` skips a sample.
+    fn load(interval: i64, series: &[(&str, &str)]) -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        for (selector, values) in series {
+            let labels = labels(selector);
+            let mut t = 0;
+            for token in values.split_whitespace() {
+                let expanded: Vec<f64> = match token.split_once('x') {
+                    Some((start, n)) => {
+                        let i = start.rfind(['+', '-']).unwrap();
+                        let (base, inc): (f64, f64) =
+                            (start[..i].parse().unwrap(), start[i..].parse().unwrap());
+                        (0..=n.parse().unwrap())
+                            .map(|k: u32| base + f64::from(k) * inc)
+                            .collect()
+                    }
+                    None if token == "_" => Vec::new(),
+                    None => vec![token.parse().unwrap()],
+                };
+                if expanded.is_empty() {
+                    t += interval;
+                }
+                for v in expanded {
+                    storage.append(&labels, t, v).unwrap();
+                    t += interval;
+                }
+            }
+        }
+        storage
+    }
+
+    /// Elements of an instant query's result, sorted by labels.
+    fn eval(storage: &MemoryStorage, q: &str, t: i64) -> Vec<(String, f64)> {
+        let (_, expr) = parse_expr(q).unwrap();
+        match Engine::new().instant_query(storage, &expr, t).unwrap() {
+            Value::Vector(v) => {
+                let mut out: Vec<(String, f64)> =
+                    v.iter().map(|s| (s.metric.to_string(), s.v)).collect();
+                out.sort_by(|a, b| a.0.cmp(&b.0));
+                out
+            }
+            v => panic!("not a vector: {:?}", v),
+        }
+    }
+
+    fn expected(elements: &[(&str, f64)]) -> Vec<(String, f64)> {
+        elements.iter().map(|(l, v)| (l.to_string(), *v)).collect()
+    }
+
+    // The cases below are ported from Prometheus'
+    // promql/promqltest/testdata/functions.test.
+
+    #[test]
+    fn test_prometheus_increase() {
+        let s = load(
+            5 * MIN,
+            &[
+                (r#"http_requests_total{path="/foo"}"#, "0+10x10"),
+                (r#"http_requests_total{path="/bar"}"#, "0+18x5 0+18x5"),
+                (r#"http_requests_total{path="/dings"}"#, "10+10x10"),
+                (r#"http_requests_total{path="/bumms"}"#, "1+10x10"),
+            ],
+        );
+
+        // The sample at 0 is outside the range, so the first sample lies one
+        // interval after its start, within 1.1 intervals, and the increase is
+        // extrapolated all the way. `/bar` resets after 90.
+        assert_eq!(
+            eval(&s, "increase(http_requests_total[50m])", 50 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 160.0),
+                (r#"{path="/bumms"}"#, 100.0),
+                (r#"{path="/dings"}"#, 100.0),
+                (r#"{path="/foo"}"#, 100.0),
+            ])
+        );
+        // "/foo" and "/bar" start at 0, so no extrapolation happens. "/dings"
+        // would reach 0 five minutes before its first sample, further than half
+        // an interval, but "/bumms" reaches it after 30s, where the
+        // extrapolation stops.
+        assert_eq!(
+            eval(&s, "increase(http_requests_total[100m])", 50 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 162.0),
+                (r#"{path="/bumms"}"#, 101.0),
+                (r#"{path="/dings"}"#, 105.0),
+                (r#"{path="/foo"}"#, 100.0),
+            ])
+        );
+        assert_eq!(
+            eval(&s, "rate(http_requests_total[50m])", 50 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 0.05333333333333334),
+                (r#"{path="/bumms"}"#, 0.03333333333333334),
+                (r#"{path="/dings"}"#, 0.03333333333333334),
+                (r#"{path="/foo"}"#, 0.03333333333333334),
+            ])
+        );
+        assert_eq!(
+            eval(&s, "delta(http_requests_total[25m])", 50 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 90.0),
+                (r#"{path="/bumms"}"#, 50.0),
+                (r#"{path="/dings"}"#, 50.0),
+                (r#"{path="/foo"}"#, 50.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_prometheus_rate_counter_resets() {
+        let s = load(
+            5 * MIN,
+            &[
+                ("testcounter_reset_middle_total", "0+27x4 0+27x5"),
+                ("testcounter_reset_end_total", "0+10x9 0 10"),
+            ],
+        );
+
+        // Counter resets in the middle of the range are handled correctly.
+        assert_eq!(
+            eval(&s, "rate(testcounter_reset_middle_total[50m])", 50 * MIN),
+            expected(&[("{}", 0.08)])
+        );
+        // A counter reset at the end of the range is ignored.
+        assert_eq!(
+            eval(&s, "rate(testcounter_reset_end_total[6m])", 50 * MIN),
+            expected(&[("{}", 0.0)])
+        );
+    }
+
+    #[test]
+    fn test_prometheus_irate_idelta() {
+        let s = load(
+            5 * MIN,
+            &[
+                (r#"http_requests_total{path="/foo"}"#, "0+10x10"),
+                (r#"http_requests_total{path="/bar"}"#, "0+10x5 0+10x5"),
+                (r#"http_requests{path="/foo"}"#, "0 50 100 150 200"),
+                (r#"http_requests{path="/bar"}"#, "0 50 100 50 200"),
+            ],
+        );
+
+        assert_eq!(
+            eval(&s, "irate(http_requests_total[50m])", 50 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 10.0 / 300.0),
+                (r#"{path="/foo"}"#, 10.0 / 300.0),
+            ])
+        );
+        // Counter reset.
+        assert_eq!(
+            eval(&s, "irate(http_requests_total[50m])", 30 * MIN),
+            expected(&[
+                (r#"{path="/bar"}"#, 0.0),
+                (r#"{path="/foo"}"#, 10.0 / 300.0),
+            ])
+        );
+        assert_eq!(
+            eval(&s, "idelta(http_requests[20m])", 20 * MIN),
+            expected(&[(r#"{path="/bar"}"#, 150.0), (r#"{path="/foo"}"#, 50.0)])
+        );
+    }
+
+    #[test]
+    fn test_prometheus_resets() {
+        let s = load(
+            5 * MIN,
+            &[
+                (r#"http_requests{path="/foo"}"#, "1 2 3 0 1 0 0 1 2 0"),
+                (r#"http_requests{path="/bar"}"#, "1 2 3 4 5 1 2 3 4 5"),
+                (r#"http_requests{path="/biz"}"#, "0 0 0 0 0 1 1 1 1 1"),
+            ],
+        );
+        let resets = |range: &str| eval(&s, &format!("resets(http_requests[{}])", range), 50 * MIN);
+
+        assert_eq!(resets("5m"), expected(&[]));
+        assert_eq!(
+            resets("20m"),
+            expected(&[
+                (r#"{path="/bar"}"#, 0.0),
+                (r#"{path="/biz"}"#, 0.0),
+                (r#"{path="/foo"}"#, 1.0),
+            ])
+        );
+        assert_eq!(
+            resets("50m"),
+            expected(&[
+                (r#"{path="/bar"}"#, 1.0),
+                (r#"{path="/biz"}"#, 0.0),
+                (r#"{path="/foo"}"#, 3.0),
+            ])
+        );
+        assert!(eval(&s, "resets(nonexistent_metric[50m])", 50 * MIN).is_empty());
+    }
+
+    #[test]
+    fn test_extrapolation_threshold() {
+        let s = load(MIN, &[("x", "10+10x4")]);
+
+        // 65s between the range start and the first sample is below 1.1 times
+        // the average interval of 60s, so the delta is extrapolated to the
+        // start; at 66s only half an interval is added.
+        assert_eq!(
+            eval(&s, "delta(x[5m5s])", 4 * MIN),
+            expected(&[("{}", 50.83333333333333)])
+        );
+        assert_eq!(
+            eval(&s, "delta(x[5m6s])", 4 * MIN),
+            expected(&[("{}", 45.0)])
+        );
+        // A counter stops where it would reach zero, 60s before its first sample.
+        assert_eq!(
+            eval(&s, "increase(x[5m5s])", 4 * MIN),
+            expected(&[("{}", 50.0)])
+        );
+    }
+
+    #[test]
+    fn test_counter_functions() {
+        let s = storage(&[
+            (r#"c{job="a"}"#, &[0.0, 10.0, 20.0, 30.0, 40.0, 50.0]),
+            (r#"r{job="a"}"#, &[5.0, 10.0, 2.0, 6.0]),
+            (r#"b{job="a"}"#, &[1.0, 11.0, 21.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "rate(c[5m])", 5 * MIN).unwrap(),
+            vec![format!(r#"{{job="a"}} {}"#, 40.0 * (300.0 / 240.0 / 300.0))]
+        );
+        assert_eq!(
+            query(&s, "increase(c[5m])", 5 * MIN).unwrap(),
+            vec![r#"{job="a"} 50"#]
+        );
+        // Counter resets add the value before the reset.
+        assert_eq!(
+            query(&s, "increase(r[3m])", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 9"#]
+        );
+        // Far from the range start, extrapolation stops where the counter
+        // would reach zero, or half an interval before the first sample.
+        assert_eq!(
+            query(&s, "increase(c[10m])", 5 * MIN).unwrap(),
+            vec![r#"{job="a"} 50"#]
+        );
+        assert_eq!(
+            query(&s, "increase(b[10m])", 2 * MIN).unwrap(),
+            vec![r#"{job="a"} 21"#]
+        );
+        assert_eq!(
+            query(&s, "rate(c[2m] offset 1m)", 5 * MIN).unwrap(),
+            query(&s, "rate(c[2m:1m])", 4 * MIN).unwrap()
+        );
+        assert!(query(&s, "rate(c[1m])", 5 * MIN).unwrap().is_empty());
+        assert_eq!(
+            query(&s, "rate(c)", 0).unwrap_err().to_string(),
+            r#"expected type range vector in call to function "rate", got instant vector"#
+        );
+    }
+
+    #[test]
+    fn test_gauge_and_instant_functions() {
+        let s = storage(&[
+            (r#"g{job="a"}"#, &[10.0, 4.0, 7.0]),
+            (r#"r{job="a"}"#, &[5.0, 10.0, 2.0, 6.0]),
+        ]);
+
+        assert_eq!(
+            query(&s, "delta(g[3m])", 2 * MIN).unwrap(),
+            vec![r#"{job="a"} -4.5"#]
+        );
+        assert_eq!(
+            query(&s, "idelta(g[3m])", 2 * MIN).unwrap(),
+            vec![r#"{job="a"} 3"#]
+        );
+        assert_eq!(
+            query(&s, "irate(r[3m])", 3 * MIN).unwrap(),
+            vec![format!(r#"{{job="a"}} {}"#, 4.0 / 60.0)]
+        );
+        assert_eq!(
+            query(&s, "irate(r[3m])", 2 * MIN).unwrap(),
+            vec![format!(r#"{{job="a"}} {}"#, 2.0 / 60.0)]
+        );
+        assert_eq!(
+            query(&s, "resets(r[5m])", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 1"#]
+        );
+        assert_eq!(
+            query(&s, "resets(r[1m])", 3 * MIN).unwrap(),
+            vec![r#"{job="a"} 0"#]
+        );
+    }
+}
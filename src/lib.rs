@@ -2,6 +2,17 @@ pub use nom::Err;
 pub use parser::parse_expr;
 
 pub mod ast;
+pub mod cost;
+pub mod diff;
+pub mod engine;
+pub mod lint;
+pub mod matchers;
 pub mod parser;
+pub mod shape;
+pub mod shard;
+pub mod split;
+pub mod storage;
+pub mod timerange;
 pub mod transformer;
+pub mod value;
 pub mod visitor;
@@ -0,0 +1,365 @@
+use crate::ast::{
+    AggregationModifierAction, Expr, FunCall, LabelMatcherOp, Path, PathSegment, Vector,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+/// A single best-practice check. Every rule can be switched off in `LintConfig`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Rule {
+    /// `rate`/`irate`/`increase` over a metric that does not look like a counter.
+    RateOnNonCounter,
+    /// A counter used as-is instead of through `rate`/`increase`.
+    CounterWithoutRate,
+    /// `rate(sum(…)[5m:])` instead of `sum(rate(…[5m]))`.
+    RateOfAggregation,
+    /// `histogram_quantile` over an aggregation that drops the `le` label.
+    HistogramQuantileWithoutLe,
+    /// A range shorter than `range_multiple` scrape intervals.
+    ShortRange,
+    /// A regex matcher without metacharacters, which could be `=`/`!=`.
+    RegexCouldBeEquality,
+    /// `absent` over an expression with more than one selector.
+    AbsentMultipleSelectors,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::RateOnNonCounter,
+        Rule::CounterWithoutRate,
+        Rule::RateOfAggregation,
+        Rule::HistogramQuantileWithoutLe,
+        Rule::ShortRange,
+        Rule::RegexCouldBeEquality,
+        Rule::AbsentMultipleSelectors,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    pub rules: HashSet<Rule>,
+    pub scrape_interval: Duration,
+    pub range_multiple: u32,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            rules: Rule::ALL.iter().copied().collect(),
+            scrape_interval: Duration::from_secs(60),
+            range_multiple: 4,
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn enable(mut self, rule: Rule) -> Self {
+        self.rules.insert(rule);
+        self
+    }
+
+    pub fn disable(mut self, rule: Rule) -> Self {
+        self.rules.remove(&rule);
+        self
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.rules.contains(&rule)
+    }
+}
+
+/// A lint finding. The AST does not keep source spans, so findings are located
+/// by the `Path` of the offending node.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Warning {
+    pub rule: Rule,
+    pub path: Path,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}: {}", self.path, self.rule, self.message)
+    }
+}
+
+const COUNTER_SUFFIXES: [&str; 4] = ["_total", "_count", "_sum", "_bucket"];
+const RATE_FUNCTIONS: [&str; 3] = ["rate", "irate", "increase"];
+const COUNTER_FUNCTIONS: [&str; 9] = [
+    "rate",
+    "irate",
+    "increase",
+    "resets",
+    "absent",
+    "absent_over_time",
+    "present_over_time",
+    "count_over_time",
+    "timestamp",
+];
+const REGEX_METACHARACTERS: &str = ".+*?()[]{}|^$\\";
+
+fn is_counter(v: &Vector) -> bool {
+    COUNTER_SUFFIXES.iter().any(|s| v.name.ends_with(s))
+}
+
+fn parent_path(path: &Path) -> Option<Path> {
+    let segments = path.segments();
+    segments
+        .split_last()
+        .map(|(_, parent)| Path(parent.to_vec()))
+}
+
+pub fn lint(expr: &Expr, config: &LintConfig) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let mut warn = |rule: Rule, path: &Path, message: String| {
+        if config.is_enabled(rule) {
+            warnings.push(Warning {
+                rule,
+                path: path.clone(),
+                message,
+            });
+        }
+    };
+
+    for (path, e) in expr.walk() {
+        match e {
+            Expr::FunCallExpr(f) => lint_funcall(&path, f, &mut warn),
+            Expr::VectorExpr(v) => {
+                let parent = parent_path(&path).and_then(|p| expr.get(&p));
+                lint_vector(&path, v, parent, config, &mut warn);
+            }
+            _ => {}
+        }
+    }
+
+    warnings
+}
+
+fn lint_funcall<F: FnMut(Rule, &Path, String)>(path: &Path, f: &FunCall, warn: &mut F) {
+    let name = f.name.as_str();
+
+    if RATE_FUNCTIONS.contains(&name) {
+        match f.args.first() {
+            Some(Expr::VectorExpr(v)) if !v.name.is_empty() && !is_counter(v) => warn(
+                Rule::RateOnNonCounter,
+                &path.child(PathSegment::Arg(0)),
+                format!(
+                    "{}() should only be applied to counters, but {} does not look like a counter (_total/_count/_sum/_bucket)",
+                    name, v.name
+                ),
+            ),
+            Some(Expr::SubQueryExpr(s)) => {
+                if let Expr::FunCallExpr(inner) = &s.expr {
+                    if inner.is_aggregation() {
+                        warn(
+                            Rule::RateOfAggregation,
+                            path,
+                            format!(
+                                "{}({}(…)) loses counter resets, use {}({}(…)) instead",
+                                name, inner.name, inner.name, name
+                            ),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if name == "histogram_quantile" {
+        if let Some(Expr::FunCallExpr(inner)) = f.args.get(1) {
+            let keeps_le = match &inner.aggregation {
+                Some(a) => match a.action {
+                    AggregationModifierAction::By => a.labels.iter().any(|l| l == "le"),
+                    AggregationModifierAction::Without => !a.labels.iter().any(|l| l == "le"),
+                },
+                None => false,
+            };
+            if inner.is_aggregation() && !keeps_le {
+                warn(
+                    Rule::HistogramQuantileWithoutLe,
+                    &path.child(PathSegment::Arg(1)),
+                    format!(
+                        "{}() drops the le label histogram_quantile() needs",
+                        inner.name
+                    ),
+                );
+            }
+        }
+    }
+
+    if name == "absent" || name == "absent_over_time" {
+        if let Some(arg) = f.args.first() {
+            if arg.selectors().count() > 1 {
+                warn(
+                    Rule::AbsentMultipleSelectors,
+                    path,
+                    format!(
+                        "{}() over several selectors only fires when the whole expression is empty",
+                        name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn lint_vector<F: FnMut(Rule, &Path, String)>(
+    path: &Path,
+    v: &Vector,
+    parent: Option<&Expr>,
+    config: &LintConfig,
+    warn: &mut F,
+) {
+    if is_counter(v) {
+        let rated = matches!(parent, Some(Expr::FunCallExpr(f)) if COUNTER_FUNCTIONS.contains(&f.name.as_str()));
+        if !rated {
+            warn(
+                Rule::CounterWithoutRate,
+                path,
+                format!(
+                    "counter {} should be wrapped in rate() or increase()",
+                    v.name
+                ),
+            );
+        }
+    }
+
+    if let Some(range) = v.range {
+        let min = config.scrape_interval * config.range_multiple;
+        if range < min {
+            warn(
+                Rule::ShortRange,
+                path,
+                format!(
+                    "range {:?} is shorter than {} scrape intervals ({:?})",
+                    range, config.range_multiple, min
+                ),
+            );
+        }
+    }
+
+    for m in &v.label_matchers {
+        let suggestion = match m.op {
+            LabelMatcherOp::Regexp => "=",
+            LabelMatcherOp::NotRegexp => "!=",
+            _ => continue,
+        };
+        if !m.value.chars().any(|c| REGEX_METACHARACTERS.contains(c)) {
+            warn(
+                Rule::RegexCouldBeEquality,
+                path,
+                format!("{} could use {} instead of {}", m, suggestion, m.op),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn rules(input: &str, config: &LintConfig) -> Vec<(Rule, String)> {
+        let (_, expr) = parse_expr(input).unwrap();
+        lint(&expr, config)
+            .into_iter()
+            .map(|w| (w.rule, w.path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_clean_query() {
+        let config = LintConfig::default();
+        assert_eq!(
+            rules(
+                r#"histogram_quantile(0.9, sum(rate(http_duration_seconds_bucket{job=~"a|b"}[5m])) by (le))"#,
+                &config
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_lint_counters() {
+        let config = LintConfig::default();
+        assert_eq!(
+            rules("rate(memory_bytes[5m]) + http_requests_total", &config),
+            vec![
+                (Rule::RateOnNonCounter, "$.lhs.args[0]".to_owned()),
+                (Rule::CounterWithoutRate, "$.rhs".to_owned()),
+            ]
+        );
+        assert_eq!(
+            rules("rate(sum(a_total)[10m:1m])", &config),
+            vec![
+                (Rule::RateOfAggregation, "$".to_owned()),
+                (
+                    Rule::CounterWithoutRate,
+                    "$.args[0].expr.args[0]".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            rules("sum(http_duration_seconds_count)", &config),
+            vec![(Rule::CounterWithoutRate, "$.args[0]".to_owned())]
+        );
+        assert_eq!(
+            rules(
+                "rate(http_duration_seconds_sum[5m]) / rate(http_duration_seconds_count[5m])",
+                &config
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_lint_histogram_quantile() {
+        let config = LintConfig::default();
+        assert_eq!(
+            rules(
+                "histogram_quantile(0.9, sum(rate(a_bucket[5m])) by (job))",
+                &config
+            ),
+            vec![(Rule::HistogramQuantileWithoutLe, "$.args[1]".to_owned())]
+        );
+        assert_eq!(
+            rules(
+                "histogram_quantile(0.9, sum(rate(a_bucket[5m])) without (le))",
+                &config
+            ),
+            vec![(Rule::HistogramQuantileWithoutLe, "$.args[1]".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_lint_matchers_ranges_and_absent() {
+        let config = LintConfig::default();
+        assert_eq!(
+            rules(
+                r#"absent(up{job=~"api"} * on (x) rate(b_total[1m]))"#,
+                &config
+            ),
+            vec![
+                (Rule::AbsentMultipleSelectors, "$".to_owned()),
+                (Rule::RegexCouldBeEquality, "$.args[0].lhs".to_owned()),
+                (Rule::ShortRange, "$.args[0].rhs.args[0]".to_owned()),
+            ]
+        );
+
+        let config = LintConfig {
+            scrape_interval: Duration::from_secs(15),
+            ..LintConfig::default()
+        }
+        .disable(Rule::RegexCouldBeEquality)
+        .disable(Rule::AbsentMultipleSelectors);
+        assert_eq!(
+            rules(
+                r#"absent(up{job=~"api"} * on (x) rate(b_total[1m]))"#,
+                &config
+            ),
+            vec![]
+        );
+    }
+}
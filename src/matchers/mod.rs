@@ -0,0 +1,278 @@
+//! Evaluation and static analysis of label matchers.
+//!
+//! A label that is not present on a series has the value `""`, so `a=""`
+//! selects series without `a`. The analyses are conservative: they only
+//! report contradictions, redundancies and subsets they can prove, treating
+//! regexes as opaque unless they are an alternation of literals like `a|b`.
+use crate::ast::{unescape, LabelMatcher, LabelMatcherOp, Vector};
+use regex::Regex;
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MatcherError {
+    #[error("invalid regex in label matcher {name}{op}{value:?}: {source}")]
+    InvalidRegex {
+        name: String,
+        op: String,
+        value: String,
+        source: regex::Error,
+    },
+}
+
+/// A label matcher ready to be applied to label values.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub name: String,
+    pub op: LabelMatcherOp,
+    /// The value with escape sequences resolved.
+    pub value: String,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    /// Compiles `m`. Regexes are fully anchored, as in Prometheus.
+    pub fn new(m: &LabelMatcher) -> Result<Self, MatcherError> {
+        let value = unescape(&m.value);
+        let regex = match m.op {
+            LabelMatcherOp::Regexp | LabelMatcherOp::NotRegexp => {
+                Some(Regex::new(&format!("^(?s:{})$", value)).map_err(|source| {
+                    MatcherError::InvalidRegex {
+                        name: m.name.clone(),
+                        op: m.op.to_string(),
+                        value: m.value.clone(),
+                        source,
+                    }
+                })?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            name: m.name.clone(),
+            op: m.op.clone(),
+            value,
+            regex,
+        })
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match (&self.op, &self.regex) {
+            (LabelMatcherOp::NotEqual, _) => value != self.value,
+            (LabelMatcherOp::Regexp, Some(re)) => re.is_match(value),
+            (LabelMatcherOp::NotRegexp, Some(re)) => !re.is_match(value),
+            _ => value == self.value,
+        }
+    }
+
+    /// The only values this matcher accepts, if there are finitely many that are
+    /// easy to tell: the value of `=`, or the branches of a literal alternation.
+    fn accepted_values(&self) -> Option<Vec<String>> {
+        match self.op {
+            LabelMatcherOp::Equal | LabelMatcherOp::None => Some(vec![self.value.clone()]),
+            LabelMatcherOp::Regexp => literal_alternatives(&self.value),
+            _ => None,
+        }
+    }
+
+    /// The only values this matcher rejects, if there are finitely many.
+    fn rejected_values(&self) -> Option<Vec<String>> {
+        match self.op {
+            LabelMatcherOp::NotEqual => Some(vec![self.value.clone()]),
+            LabelMatcherOp::NotRegexp => literal_alternatives(&self.value),
+            _ => None,
+        }
+    }
+
+    fn matches_everything(&self) -> bool {
+        self.op == LabelMatcherOp::Regexp && (self.value == ".*" || self.value == "(?s:.*)")
+    }
+}
+
+fn literal_alternatives(regex: &str) -> Option<Vec<String>> {
+    if regex.chars().any(|c| ".+*?()[]{}^$\\".contains(c)) {
+        return None;
+    }
+    Some(regex.split('|').map(|s| s.to_owned()).collect())
+}
+
+pub fn compile(matchers: &[LabelMatcher]) -> Result<Vec<Matcher>, MatcherError> {
+    matchers.iter().map(Matcher::new).collect()
+}
+
+/// All matchers of a selector, including the metric name as a `__name__` matcher.
+pub fn selector_matchers(v: &Vector) -> Vec<LabelMatcher> {
+    let mut matchers = Vec::with_capacity(v.label_matchers.len() + 1);
+    if !v.name.is_empty() {
+        matchers.push(LabelMatcher {
+            op: LabelMatcherOp::Equal,
+            name: "__name__".to_owned(),
+            value: v.name.clone(),
+        });
+    }
+    matchers.extend(v.label_matchers.iter().cloned());
+    matchers
+}
+
+fn names(matchers: &[Matcher]) -> BTreeSet<&str> {
+    matchers.iter().map(|m| m.name.as_str()).collect()
+}
+
+fn on_label<'a>(matchers: &'a [Matcher], name: &str) -> Vec<&'a Matcher> {
+    matchers.iter().filter(|m| m.name == name).collect()
+}
+
+/// Values the matchers on one label can accept, if that set is finite and known.
+fn possible_values(on_label: &[&Matcher]) -> Option<Vec<String>> {
+    let candidates = on_label.iter().find_map(|m| m.accepted_values())?;
+    Some(
+        candidates
+            .into_iter()
+            .filter(|v| on_label.iter().all(|m| m.matches(v)))
+            .collect(),
+    )
+}
+
+fn label_unsatisfiable(on_label: &[&Matcher]) -> bool {
+    if let Some(values) = possible_values(on_label) {
+        return values.is_empty();
+    }
+    // `a=~"r"` together with `a!~"r"`.
+    on_label.iter().any(|m| {
+        m.op == LabelMatcherOp::Regexp
+            && on_label
+                .iter()
+                .any(|n| n.op == LabelMatcherOp::NotRegexp && n.value == m.value)
+    })
+}
+
+fn unsatisfiable(matchers: &[Matcher]) -> bool {
+    names(matchers)
+        .into_iter()
+        .any(|name| label_unsatisfiable(&on_label(matchers, name)))
+}
+
+/// Whether no series can ever satisfy all of `matchers`, e.g. `a="x", a="y"`.
+pub fn is_unsatisfiable(matchers: &[LabelMatcher]) -> Result<bool, MatcherError> {
+    Ok(unsatisfiable(&compile(matchers)?))
+}
+
+/// Removes duplicate matchers, matchers accepting everything (`a=~".*"`) and
+/// matchers implied by an equality on the same label (`a=~"x|y"` next to `a="x"`).
+/// The remaining matchers keep their order.
+pub fn simplify(matchers: &[LabelMatcher]) -> Result<Vec<LabelMatcher>, MatcherError> {
+    let compiled = compile(matchers)?;
+    let mut out: Vec<LabelMatcher> = Vec::with_capacity(matchers.len());
+
+    for (m, c) in matchers.iter().zip(&compiled) {
+        if out.contains(m) || c.matches_everything() {
+            continue;
+        }
+        let equal = compiled
+            .iter()
+            .find(|e| e.name == c.name && e.op == LabelMatcherOp::Equal);
+        if let Some(e) = equal {
+            if c.op != LabelMatcherOp::Equal && c.matches(&e.value) {
+                continue;
+            }
+        }
+        out.push(m.clone());
+    }
+    Ok(out)
+}
+
+/// Whether `m` accepts every value that all of `on_label` accept.
+fn implied(on_label: &[&Matcher], m: &Matcher) -> bool {
+    if m.matches_everything() || on_label.iter().any(|a| a.op == m.op && a.value == m.value) {
+        return true;
+    }
+    if let Some(values) = possible_values(on_label) {
+        return values.iter().all(|v| m.matches(v));
+    }
+    if let Some(rejected) = m.rejected_values() {
+        return rejected
+            .iter()
+            .all(|v| on_label.iter().any(|a| !a.matches(v)));
+    }
+    false
+}
+
+/// Whether every series selected by `a` is also selected by `b`, e.g. whether a
+/// cached result for `b` can answer a query for `a`. Only answers `true` when
+/// this can be proven.
+pub fn is_subset(a: &[LabelMatcher], b: &[LabelMatcher]) -> Result<bool, MatcherError> {
+    let a = compile(a)?;
+    let b = compile(b)?;
+
+    Ok(unsatisfiable(&a) || b.iter().all(|m| implied(&on_label(&a, &m.name), m)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expr;
+    use crate::parse_expr;
+
+    fn matchers(selector: &str) -> Vec<LabelMatcher> {
+        match parse_expr(selector).unwrap().1 {
+            Expr::VectorExpr(v) => selector_matchers(&v),
+            e => panic!("not a selector: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_matcher() {
+        let m = Matcher::new(&matchers(r#"{a=~"x|y\\.z"}"#)[0]).unwrap();
+        assert!(m.matches("x"));
+        assert!(m.matches("y.z"));
+        assert!(!m.matches("yaz"));
+        assert!(!m.matches("xx"));
+
+        let m = Matcher::new(&matchers(r#"{a!=""}"#)[0]).unwrap();
+        assert!(!m.matches(""));
+        assert!(Matcher::new(&matchers(r#"{a=~"("}"#)[0]).is_err());
+    }
+
+    #[test]
+    fn test_is_unsatisfiable() {
+        let unsat = |s: &str| is_unsatisfiable(&matchers(s)).unwrap();
+
+        assert!(unsat(r#"{a="x", a="y"}"#));
+        assert!(unsat(r#"{a="x", a!~"x|y"}"#));
+        assert!(unsat(r#"up{__name__="down"}"#));
+        assert!(unsat(r#"{a=~"x|y", a!~"x|y"}"#));
+        assert!(unsat(r#"{a=~"x.*", a!~"x.*"}"#));
+        assert!(!unsat(r#"{a="x", a=~"x|y", b!="x"}"#));
+        assert!(!unsat(r#"{a=~"x.*", a!="xy"}"#));
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(
+            simplify(&matchers(r#"up{a="x", a=~".*", a=~"x|y", b!="c", b!="c"}"#)).unwrap(),
+            matchers(r#"up{a="x", b!="c"}"#)
+        );
+        assert_eq!(
+            simplify(&matchers(r#"{a=~"x.*", a!="y"}"#)).unwrap(),
+            matchers(r#"{a=~"x.*", a!="y"}"#)
+        );
+    }
+
+    #[test]
+    fn test_is_subset() {
+        let subset = |a: &str, b: &str| is_subset(&matchers(a), &matchers(b)).unwrap();
+
+        assert!(subset(
+            r#"up{job="api", env="prod"}"#,
+            r#"up{job=~"api|web"}"#
+        ));
+        assert!(!subset(
+            r#"up{job=~"api|web"}"#,
+            r#"up{job="api", env="prod"}"#
+        ));
+        assert!(subset(r#"up{a!~"x|y"}"#, r#"up{a!="x"}"#));
+        assert!(subset(r#"up{a=~"x.+"}"#, r#"{a=~"x.+", a=~".*"}"#));
+        assert!(!subset(r#"up{a=~"x.+"}"#, r#"up{a=~"x.*"}"#));
+        assert!(subset(r#"up{a="x", a="y"}"#, r#"down"#));
+    }
+}
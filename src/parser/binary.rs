@@ -154,6 +154,18 @@ mod tests {
             )),
         );
 
+        assert_eq!(
+            parse_and_unless("a unless b"),
+            Ok((
+                "",
+                binary_expr(
+                    BinaryOp::Unless(None),
+                    vector_expr(vector("a")),
+                    vector_expr(vector("b")),
+                )
+            )),
+        );
+
         assert_eq!(
             parse_and_unless("a and b and c"),
             Ok((
@@ -279,7 +291,6 @@ mod tests {
                         name: "b".to_owned(),
                         ..Default::default()
                     })),
-                    ..Default::default()
                 }))
             ))
         );
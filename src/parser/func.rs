@@ -68,7 +68,6 @@ fn parse_function_call_body(input: &str) -> IResult<&str, Vec<Expr>, Error<&str>
             |a| a,
         ),
     )(input)
-    .map(|(input, a)| (input, a))
 }
 
 fn parse_function_call_args(input: &str) -> IResult<&str, Vec<Expr>, Error<&str>> {
@@ -38,7 +38,7 @@ pub fn parse_grouping_label_list(input: &str) -> IResult<&str, Vec<String>, Erro
 
 pub fn parse_grouping_label(input: &str) -> IResult<&str, &str, Error<&str>> {
     debug!("parse_grouping_label: {}", input);
-    context("grouping_label", parse_label_name)(input).map(|(input, a)| (input, a))
+    context("grouping_label", parse_label_name)(input)
 }
 
 #[cfg(test)]
@@ -5,6 +5,7 @@ use log::debug;
 use nom::branch::alt;
 use nom::bytes::complete::{escaped, is_not, tag};
 use nom::character::complete::one_of;
+use nom::combinator::{map, opt};
 use nom::number::complete::double;
 use nom::sequence::delimited;
 use nom::IResult;
@@ -20,17 +21,26 @@ pub fn parse_string_literal(input: &str) -> IResult<&str, StringLiteral, Error<&
     alt((
         delimited(
             ws(tag("\"")),
-            escaped(is_not("\"\\"), '\\', one_of("\"\\")),
+            map(
+                opt(escaped(is_not("\"\\"), '\\', one_of("\"\\"))),
+                Option::unwrap_or_default,
+            ),
             ws(tag("\"")),
         ),
         delimited(
             ws(tag("'")),
-            escaped(is_not("'\\"), '\\', one_of("'\\")),
+            map(
+                opt(escaped(is_not("'\\"), '\\', one_of("'\\"))),
+                Option::unwrap_or_default,
+            ),
             ws(tag("'")),
         ),
         delimited(
             ws(tag("`")),
-            escaped(is_not("`\\"), '\\', one_of("`\\")),
+            map(
+                opt(escaped(is_not("`\\"), '\\', one_of("`\\"))),
+                Option::unwrap_or_default,
+            ),
             ws(tag("`")),
         ),
     ))(input)
@@ -129,6 +139,12 @@ mod tests {
             ))
         );
 
+        assert_eq!(
+            parse_string_literal(r#" "" "#),
+            Ok(("", string_literal("")))
+        );
+        assert_eq!(parse_string_literal("''"), Ok(("", string_literal(""))));
+
         assert_eq!(
             parse_string_literal(r#" "ab cd _ 123" "#),
             Ok((
@@ -1,6 +1,7 @@
 use crate::ast::{Expr, SubqueryExpr};
 use nom::branch::alt;
 use nom::bytes::complete::tag;
+use nom::character::complete::multispace0;
 use nom::combinator::{map, opt};
 use nom::sequence::{delimited, preceded, tuple};
 use nom::{IResult, Parser};
@@ -50,6 +51,7 @@ pub fn parse_expr(input: &str) -> IResult<&str, Expr, Error<&str>> {
 }
 
 pub(crate) fn parse_atom(input: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (input, _) = multispace0(input)?;
     alt((
         delimited(tag("("), parse_expr, tag(")")),
         ws(parse_number_literal).map(|e| Expr::NumberLiteralExpr(Box::new(e))),
@@ -87,6 +89,7 @@ mod tests {
                     label_matchers: vec![],
                     offset: Some(Duration::from_secs(60 * 10)),
                     range: Some(Duration::from_secs(60)),
+                    at: None,
                 }
             ))
         );
@@ -216,6 +219,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_function_args_with_spaces() {
+        assert_eq!(
+            parse_expr("f(0.9, sum(a) by (job), (b))"),
+            Ok((
+                "",
+                fun_call_expr(fun_call(
+                    "f",
+                    vec![
+                        number_literal_expr(0.9),
+                        fun_call_expr(fun_call_agg(
+                            "sum",
+                            vec![vector_expr(vector("a"))],
+                            Some(AggregationModifier {
+                                action: AggregationModifierAction::By,
+                                labels: vec!["job".to_owned()]
+                            })
+                        )),
+                        vector_expr(vector("b")),
+                    ]
+                )),
+            ))
+        );
+    }
+
     #[test]
     fn test_parse_expr3() {
         // "sum by (bar) (foo) * count without (bar) (foo)"
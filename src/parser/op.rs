@@ -119,7 +119,7 @@ pub(crate) fn parse_binary_op_compare(input: &str) -> IResult<&str, BinaryOp, Er
 
 pub fn parse_binary_op_and_unless(input: &str) -> IResult<&str, BinaryOp, Error<&str>> {
     tuple((
-        ws(alt((tag("and"), tag("less")))),
+        ws(alt((tag("and"), tag("unless")))),
         opt(parse_binary_modifier),
     ))(input)
     .map(|(input, (a, b))| match b {
@@ -1,4 +1,4 @@
-use crate::ast::selector::{LabelMatcher, LabelMatcherOp, Vector};
+use crate::ast::selector::{AtModifier, LabelMatcher, LabelMatcherOp, Vector};
 use crate::parser::error::{Error, ParserError};
 use crate::parser::literal::parse_string_literal;
 use crate::parser::{parse_label_name, parse_metric_name, ws};
@@ -8,6 +8,7 @@ use nom::bytes::complete::{tag, tag_no_case, take_while};
 use nom::character::is_alphanumeric;
 use nom::combinator::{map, opt};
 use nom::multi::separated_list0;
+use nom::number::complete::double;
 use nom::sequence::{delimited, preceded, tuple};
 use nom::Err::Failure;
 use nom::IResult;
@@ -17,15 +18,52 @@ pub(crate) fn parse_vector(input: &str) -> IResult<&str, Vector, Error<&str>> {
     tuple((
         parse_instant_vec,
         opt(delimited(tag("["), parse_duration, tag("]"))),
-        opt(preceded(ws(tag_no_case("offset")), parse_duration)),
+        opt(alt((
+            map(tuple((parse_offset, opt(parse_at_modifier))), |(o, at)| {
+                (Some(o), at)
+            }),
+            map(tuple((parse_at_modifier, opt(parse_offset))), |(at, o)| {
+                (o, Some(at))
+            }),
+        ))),
     ))(input)
-    .map(|(input, (mut v, range, offset))| {
+    .map(|(input, (mut v, range, modifiers))| {
         v.range = range;
-        v.offset = offset;
+        if let Some((offset, at)) = modifiers {
+            v.offset = offset;
+            v.at = at;
+        }
         (input, v)
     })
 }
 
+fn parse_offset(input: &str) -> IResult<&str, Duration, Error<&str>> {
+    preceded(ws(tag_no_case("offset")), parse_duration)(input)
+}
+
+// at_modifier : AT number_literal
+// | AT START LEFT_PAREN RIGHT_PAREN
+// | AT END LEFT_PAREN RIGHT_PAREN
+// ;
+pub(crate) fn parse_at_modifier(input: &str) -> IResult<&str, AtModifier, Error<&str>> {
+    preceded(
+        ws(tag("@")),
+        alt((
+            map(
+                tuple((ws(tag_no_case("start")), ws(tag("(")), ws(tag(")")))),
+                |_| AtModifier::Start,
+            ),
+            map(
+                tuple((ws(tag_no_case("end")), ws(tag("(")), ws(tag(")")))),
+                |_| AtModifier::End,
+            ),
+            map(ws(double), |ts| {
+                AtModifier::Timestamp((ts * 1000.0).round() as i64)
+            }),
+        )),
+    )(input)
+}
+
 fn parse_instant_vec(input: &str) -> IResult<&str, Vector, Error<&str>> {
     debug!("parse_instant_vec: {}", input);
     map(
@@ -44,7 +82,6 @@ pub fn parse_label_matchers(input: &str) -> IResult<&str, Vec<LabelMatcher>, Err
         separated_list0(tag(","), parse_label_matcher),
         ws(tag("}")),
     )(input)
-    .map(|(input, a)| (input, a))
 }
 
 pub fn parse_label_matcher(input: &str) -> IResult<&str, LabelMatcher, Error<&str>> {
@@ -181,6 +218,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_at_modifier() {
+        assert_eq!(
+            parse_vector("a[5m] @ 1609746000.5 offset 1m"),
+            Ok((
+                "",
+                Vector {
+                    name: "a".to_owned(),
+                    range: Some(Duration::from_secs(300)),
+                    offset: Some(Duration::from_secs(60)),
+                    at: Some(AtModifier::Timestamp(1_609_746_000_500)),
+                    ..Default::default()
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse_vector("a offset 1m @ start()"),
+            Ok((
+                "",
+                Vector {
+                    name: "a".to_owned(),
+                    offset: Some(Duration::from_secs(60)),
+                    at: Some(AtModifier::Start),
+                    ..Default::default()
+                }
+            ))
+        );
+
+        assert_eq!(parse_at_modifier("@ end ( )"), Ok(("", AtModifier::End)));
+    }
+
     #[test]
     fn test_parse_duration() {
         assert_eq!(parse_duration("5m"), Ok(("", Duration::from_secs(60 * 5))))
@@ -0,0 +1,195 @@
+//! Query shapes: the structure of a query with its literal values stripped, for
+//! grouping logged queries.
+use crate::ast::{
+    fnv1a_64, v, AtModifier, Expr, NumberLiteral, StringLiteral, SubqueryExpr, Vector,
+};
+use crate::transformer::{transform, Renamer, Transformer};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+/// What to keep verbatim in a shape. Label values, string arguments and numbers
+/// are always replaced by `?`, durations by `0s` and `@` timestamps by `0`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ShapeOptions {
+    /// Otherwise metric names become `metric1`, `metric2`, … in order of appearance.
+    pub keep_metric_names: bool,
+    /// Otherwise label names become `label1`, `label2`, … in order of appearance.
+    pub keep_label_names: bool,
+}
+
+impl Default for ShapeOptions {
+    fn default() -> Self {
+        Self {
+            keep_metric_names: true,
+            keep_label_names: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct QueryShape {
+    /// Canonical template, e.g. `rate(http{job="?"}[0s])`.
+    pub template: String,
+    /// Stable FNV-1a hash of `template`.
+    pub hash: u64,
+}
+
+/// Computes the shape of `expr`. Queries that only differ in literal values, or
+/// in how they order matchers and grouping labels, have the same shape.
+pub fn shape(expr: &Expr, opts: &ShapeOptions) -> QueryShape {
+    let expr = expr.normalize();
+
+    let mut renamer = Renamer::new();
+    if !opts.keep_metric_names {
+        let mut seen = HashMap::new();
+        for (_, v) in expr.selectors() {
+            if !v.name.is_empty() && !seen.contains_key(&v.name) {
+                let placeholder = format!("metric{}", seen.len() + 1);
+                renamer = renamer.metric(&v.name, &placeholder);
+                seen.insert(v.name.clone(), placeholder);
+            }
+        }
+    }
+    if !opts.keep_label_names {
+        let mut seen = HashMap::new();
+        for label in label_names(&expr) {
+            if label != "__name__" && !seen.contains_key(&label) {
+                let placeholder = format!("label{}", seen.len() + 1);
+                renamer = renamer.label(&label, &placeholder);
+                seen.insert(label, placeholder);
+            }
+        }
+    }
+    let expr = match transform(&expr, &mut renamer) {
+        Ok(e) => e,
+        Err(e) => match e {},
+    };
+    let template = match transform(&expr, &mut Placeholders) {
+        Ok(e) => e.to_string(),
+        Err(e) => match e {},
+    };
+    QueryShape {
+        hash: fnv1a_64(template.as_bytes()),
+        template,
+    }
+}
+
+/// Label names in matchers and grouping clauses, in pre-order.
+fn label_names(expr: &Expr) -> Vec<String> {
+    let mut out = Vec::new();
+    for (_, e) in expr.walk() {
+        match e {
+            Expr::VectorExpr(v) => out.extend(v.label_matchers.iter().map(|m| m.name.clone())),
+            Expr::FunCallExpr(f) => out.extend(f.aggregation.iter().flat_map(|a| a.labels.clone())),
+            Expr::BinaryExpr(b) => {
+                if let Some(m) = b.op.modifier() {
+                    out.extend(m.labels.iter().cloned());
+                    out.extend(m.group.iter().flat_map(|g| g.labels.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Replaces every literal value with a placeholder, so that printing the tree
+/// gives its template.
+struct Placeholders;
+
+impl Transformer for Placeholders {
+    type Err = Infallible;
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        let mut v = ast.clone();
+        for m in v.label_matchers.iter_mut() {
+            m.value = "?".to_owned();
+        }
+        v.range = v.range.map(|_| Duration::ZERO);
+        v.offset = v.offset.map(|_| Duration::ZERO);
+        if let Some(AtModifier::Timestamp(_)) = v.at {
+            v.at = Some(AtModifier::Timestamp(0));
+        }
+        Ok(Expr::VectorExpr(Box::new(v)))
+    }
+
+    fn transform_number_literal(&mut self, _: &NumberLiteral) -> Result<Expr, Self::Err> {
+        // Numbers have no textual placeholder of their own; a selector named `?`
+        // prints as one and binds like a number.
+        Ok(Expr::VectorExpr(Box::new(v("?"))))
+    }
+
+    fn transform_string_literal(&mut self, _: &StringLiteral) -> Result<Expr, Self::Err> {
+        Ok(Expr::StringLiteralExpr(Box::new(StringLiteral {
+            value: "?".to_owned(),
+        })))
+    }
+
+    fn transform_subquery_expr(&mut self, ast: &SubqueryExpr) -> Result<Expr, Self::Err> {
+        Ok(Expr::SubQueryExpr(Box::new(SubqueryExpr {
+            expr: self.transform_expr(&ast.expr)?,
+            range: ast.range.map(|_| Duration::ZERO),
+            resolution: ast.resolution.map(|_| Duration::ZERO),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn template(input: &str, opts: &ShapeOptions) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        shape(&expr, opts).template
+    }
+
+    #[test]
+    fn test_shape_groups_literal_values() {
+        let opts = ShapeOptions::default();
+        let (_, a) = parse_expr(r#"rate(http{job="a"}[5m])"#).unwrap();
+        let (_, b) = parse_expr(r#"rate(http{job="b"}[1m])"#).unwrap();
+
+        assert_eq!(shape(&a, &opts), shape(&b, &opts));
+        assert_eq!(shape(&a, &opts).template, r#"rate(http{job="?"}[0s])"#);
+        assert_eq!(
+            template(
+                r#"histogram_quantile(0.9, sum by (le, job) (rate(a{x=~"y.*", b="1"}[5m] offset 1h))) > 2"#,
+                &opts
+            ),
+            r#"histogram_quantile(?, sum by (job, le) (rate(a{b="?", x=~"?"}[0s] offset 0s))) > ?"#
+        );
+        assert_eq!(
+            template(r#"max_over_time(-rate(a[5m] @ 100)[1h:1m])"#, &opts),
+            r#"max_over_time((-rate(a[0s] @ 0))[0s:0s])"#
+        );
+        assert_eq!(
+            template("(a + 1) * (2 ^ 3) ^ -b", &opts),
+            "(a + ?) * (? ^ ?) ^ -b"
+        );
+    }
+
+    #[test]
+    fn test_shape_placeholder_names() {
+        let opts = ShapeOptions {
+            keep_metric_names: false,
+            keep_label_names: false,
+        };
+        assert_eq!(
+            template(
+                r#"sum by (job) (rate(errors{job="a"}[5m])) / on (job) sum by (job) (rate(requests[5m]))"#,
+                &opts
+            ),
+            r#"sum by (label1) (rate(metric1{label1="?"}[0s])) / on (label1) sum by (label1) (rate(metric2[0s]))"#
+        );
+
+        let opts = ShapeOptions {
+            keep_metric_names: false,
+            ..ShapeOptions::default()
+        };
+        let (_, a) = parse_expr("foo / foo").unwrap();
+        let (_, b) = parse_expr("foo / bar").unwrap();
+        assert_ne!(shape(&a, &opts).hash, shape(&b, &opts).hash);
+    }
+}
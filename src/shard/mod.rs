@@ -0,0 +1,455 @@
+//! Vertical sharding of queries by series, as done by Cortex-style query frontends.
+//!
+//! Every selector below a shardable aggregation gets a `__query_shard__="i_of_n"`
+//! matcher, so storage only returns the series hashing into shard `i`. The
+//! aggregation is computed once per shard, each partial result is tagged with
+//! its shard through `label_replace` so that `or` keeps them apart, and an outer
+//! aggregation merges the partials back together.
+use crate::ast::{
+    binary_expr, AggregationModifier, AggregationModifierAction, BinaryOp, Expr, FunCall,
+    LabelMatcher, LabelMatcherOp, Path, PathSegment, StringLiteral, Vector,
+};
+use crate::transformer::{transform, Transformer};
+use std::convert::Infallible;
+use std::fmt;
+
+/// Label carrying the shard selector, e.g. `__query_shard__="1_of_16"`.
+pub const SHARD_LABEL: &str = "__query_shard__";
+
+/// Functions that combine samples of different series, and so would return
+/// different results when evaluated per shard.
+const CROSS_SERIES_FUNCTIONS: [&str; 9] = [
+    "absent",
+    "absent_over_time",
+    "histogram_quantile",
+    "scalar",
+    "vector",
+    "sort",
+    "sort_desc",
+    "sort_by_label",
+    "sort_by_label_desc",
+];
+
+/// Explains why the node at `path` prevents sharding.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NotShardable {
+    pub path: Path,
+    pub reason: String,
+}
+
+impl fmt::Display for NotShardable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+/// Result of `analyze`: the aggregations that will be sharded, and for the parts
+/// of the query that can't be, the reasons why.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Shardability {
+    pub shardable: Vec<Path>,
+    pub reasons: Vec<NotShardable>,
+}
+
+impl Shardability {
+    pub fn is_shardable(&self) -> bool {
+        !self.shardable.is_empty()
+    }
+}
+
+pub fn analyze(expr: &Expr) -> Shardability {
+    let mut result = Shardability::default();
+    analyze_node(expr, Path::root(), false, &mut result);
+    result
+}
+
+/// A query rewritten by `shard`, along with the reasons for the parts of it
+/// that still run unsharded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sharded {
+    pub expr: Expr,
+    pub reasons: Vec<NotShardable>,
+}
+
+/// Rewrites `expr` so that it runs over `shards` shards and merges the partial results.
+///
+/// Fails with the reasons from `analyze` if no part of the query can be sharded,
+/// and always with at least one reason.
+pub fn shard(expr: &Expr, shards: usize) -> Result<Sharded, Vec<NotShardable>> {
+    if shards < 2 {
+        return Err(vec![not_shardable(
+            &Path::root(),
+            "shard count must be at least 2".to_owned(),
+        )]);
+    }
+    let mut analysis = analyze(expr);
+    if !analysis.is_shardable() {
+        if analysis.reasons.is_empty() {
+            analysis.reasons.push(not_shardable(
+                &Path::root(),
+                "query has no aggregation to shard".to_owned(),
+            ));
+        }
+        return Err(analysis.reasons);
+    }
+    Ok(Sharded {
+        expr: rewrite(expr, &Path::root(), &analysis.shardable, shards),
+        reasons: analysis.reasons,
+    })
+}
+
+fn not_shardable(path: &Path, reason: String) -> NotShardable {
+    NotShardable {
+        path: path.clone(),
+        reason,
+    }
+}
+
+/// `explained` is set below an aggregation that was already reported as not
+/// shardable, so its selectors don't get a reason of their own.
+fn analyze_node(expr: &Expr, path: Path, explained: bool, result: &mut Shardability) {
+    if let Expr::FunCallExpr(f) = expr {
+        if f.is_aggregation() {
+            let reason = match check_aggregation(f, &path) {
+                Ok(inner) => {
+                    let arg = f.args.len() - 1;
+                    match check_per_series(inner, path.child(PathSegment::Arg(arg))) {
+                        Ok(()) => {
+                            result.shardable.push(path);
+                            return;
+                        }
+                        Err(reason) => reason,
+                    }
+                }
+                Err(reason) => reason,
+            };
+            // Aggregations further down, e.g. the sum() in max(sum by (x) (…)),
+            // may still be shardable on their own.
+            result.reasons.push(reason);
+            expr.children()
+                .for_each(|(seg, child)| analyze_node(child, path.child(seg), true, result));
+            return;
+        }
+    }
+
+    match expr {
+        Expr::VectorExpr(_) if !explained => result.reasons.push(not_shardable(
+            &path,
+            "selector is not below a shardable aggregation".to_owned(),
+        )),
+        _ => expr
+            .children()
+            .for_each(|(seg, child)| analyze_node(child, path.child(seg), explained, result)),
+    }
+}
+
+fn check_aggregation<'a>(f: &'a FunCall, path: &Path) -> Result<&'a Expr, NotShardable> {
+    match f.name.as_str() {
+        "sum" | "count" | "min" | "max" | "avg" | "group" | "topk" | "bottomk" => f
+            .args
+            .last()
+            .ok_or_else(|| not_shardable(path, format!("{}() without arguments", f.name))),
+        _ => Err(not_shardable(
+            path,
+            format!("{}() cannot be merged from partial results", f.name),
+        )),
+    }
+}
+
+/// Checks that `expr` computes every output series from a single input series,
+/// so evaluating it on a subset of series yields a subset of the result.
+fn check_per_series(expr: &Expr, path: Path) -> Result<(), NotShardable> {
+    match expr {
+        Expr::FunCallExpr(f) if f.is_aggregation() => Err(not_shardable(
+            &path,
+            format!(
+                "nested aggregation {}() groups series across shards",
+                f.name
+            ),
+        )),
+        Expr::FunCallExpr(f) if CROSS_SERIES_FUNCTIONS.contains(&f.name.as_str()) => Err(
+            not_shardable(&path, format!("{}() combines series across shards", f.name)),
+        ),
+        Expr::BinaryExpr(b) if has_selectors(&b.lhs) && has_selectors(&b.rhs) => {
+            Err(not_shardable(
+                &path,
+                format!(
+                    "'{}' between two vectors matches series across shards",
+                    b.op.symbol()
+                ),
+            ))
+        }
+        _ => expr
+            .children()
+            .try_for_each(|(seg, child)| check_per_series(child, path.child(seg))),
+    }
+}
+
+fn has_selectors(expr: &Expr) -> bool {
+    expr.selectors().next().is_some()
+}
+
+fn rewrite(expr: &Expr, path: &Path, shardable: &[Path], shards: usize) -> Expr {
+    if let Expr::FunCallExpr(f) = expr {
+        if shardable.contains(path) {
+            return shard_aggregation(f, shards);
+        }
+    }
+
+    match expr {
+        Expr::BinaryExpr(b) => {
+            let lhs = rewrite(&b.lhs, &path.child(PathSegment::Lhs), shardable, shards);
+            let rhs = rewrite(&b.rhs, &path.child(PathSegment::Rhs), shardable, shards);
+            binary_expr(b.op.clone(), lhs, rhs)
+        }
+        Expr::FunCallExpr(f) => {
+            let args = f
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| rewrite(arg, &path.child(PathSegment::Arg(i)), shardable, shards))
+                .collect();
+            Expr::FunCallExpr(Box::new(FunCall {
+                args,
+                ..f.as_ref().clone()
+            }))
+        }
+        Expr::SubQueryExpr(s) => {
+            let mut s = s.as_ref().clone();
+            s.expr = rewrite(&s.expr, &path.child(PathSegment::Inner), shardable, shards);
+            Expr::SubQueryExpr(Box::new(s))
+        }
+        Expr::NegationExpr(e) => Expr::NegationExpr(Box::new(rewrite(
+            e,
+            &path.child(PathSegment::Inner),
+            shardable,
+            shards,
+        ))),
+        _ => expr.clone(),
+    }
+}
+
+struct ShardMatcher {
+    value: String,
+}
+
+impl Transformer for ShardMatcher {
+    type Err = Infallible;
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        let mut v = ast.clone();
+        v.label_matchers.push(LabelMatcher {
+            op: LabelMatcherOp::Equal,
+            name: SHARD_LABEL.to_owned(),
+            value: self.value.clone(),
+        });
+        Ok(Expr::VectorExpr(Box::new(v)))
+    }
+}
+
+fn string(s: &str) -> Expr {
+    Expr::StringLiteralExpr(Box::new(StringLiteral::new(s.to_owned())))
+}
+
+fn label_replace(expr: Expr, value: &str) -> Expr {
+    Expr::FunCallExpr(Box::new(FunCall {
+        name: "label_replace".to_owned(),
+        args: vec![
+            expr,
+            string(SHARD_LABEL),
+            string(value),
+            string(""),
+            string(""),
+        ],
+        aggregation: None,
+    }))
+}
+
+fn aggregate(
+    name: &str,
+    mut args: Vec<Expr>,
+    inner: Expr,
+    grouping: &Option<AggregationModifier>,
+) -> Expr {
+    args.push(inner);
+    Expr::FunCallExpr(Box::new(FunCall {
+        name: name.to_owned(),
+        args,
+        aggregation: grouping.clone(),
+    }))
+}
+
+/// Concatenates the per-shard partials of `name`, each tagged with its shard.
+fn partials(f: &FunCall, name: &str, shards: usize) -> Expr {
+    let (params, inner) = f.args.split_at(f.args.len() - 1);
+    (1..=shards)
+        .map(|i| {
+            let value = format!("{}_of_{}", i, shards);
+            let mut t = ShardMatcher {
+                value: value.clone(),
+            };
+            let inner = match transform(&inner[0], &mut t) {
+                Ok(e) => e,
+                Err(e) => match e {},
+            };
+            label_replace(
+                aggregate(name, params.to_vec(), inner, &f.aggregation),
+                &value,
+            )
+        })
+        .reduce(|acc, e| binary_expr(BinaryOp::Or(None), acc, e))
+        .expect("at least one shard")
+}
+
+fn shard_aggregation(f: &FunCall, shards: usize) -> Expr {
+    // The outer aggregation must also drop the shard label when grouping `without`.
+    let merge_grouping = f.aggregation.clone().map(|mut a| {
+        if a.action == AggregationModifierAction::Without {
+            a.labels.push(SHARD_LABEL.to_owned());
+        }
+        a
+    });
+    let params = f.args[..f.args.len() - 1].to_vec();
+
+    match f.name.as_str() {
+        "count" => aggregate("sum", vec![], partials(f, "count", shards), &merge_grouping),
+        "avg" => binary_expr(
+            BinaryOp::Div(None),
+            aggregate("sum", vec![], partials(f, "sum", shards), &merge_grouping),
+            aggregate("sum", vec![], partials(f, "count", shards), &merge_grouping),
+        ),
+        "topk" | "bottomk" => label_replace(
+            aggregate(
+                &f.name,
+                params,
+                partials(f, &f.name, shards),
+                &merge_grouping,
+            ),
+            "",
+        ),
+        name => aggregate(name, params, partials(f, name, shards), &merge_grouping),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn reasons(input: &str) -> Vec<String> {
+        let (_, expr) = parse_expr(input).unwrap();
+        analyze(&expr)
+            .reasons
+            .iter()
+            .map(|r| r.to_string())
+            .collect()
+    }
+
+    fn sharded(input: &str, shards: usize) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        shard(&expr, shards).unwrap().expr.to_string()
+    }
+
+    #[test]
+    fn test_shard_sum() {
+        assert_eq!(
+            sharded("sum(rate(a[5m])) by (job)", 2),
+            r#"sum by (job) (label_replace(sum by (job) (rate(a{__query_shard__="1_of_2"}[5m])), "__query_shard__", "1_of_2", "", "") or label_replace(sum by (job) (rate(a{__query_shard__="2_of_2"}[5m])), "__query_shard__", "2_of_2", "", ""))"#
+        );
+        assert_eq!(
+            sharded("count(a) without (x)", 2),
+            r#"sum without (x, __query_shard__) (label_replace(count without (x) (a{__query_shard__="1_of_2"}), "__query_shard__", "1_of_2", "", "") or label_replace(count without (x) (a{__query_shard__="2_of_2"}), "__query_shard__", "2_of_2", "", ""))"#
+        );
+    }
+
+    #[test]
+    fn test_shard_avg_and_topk() {
+        assert_eq!(
+            sharded("avg(a)", 2),
+            r#"sum(label_replace(sum(a{__query_shard__="1_of_2"}), "__query_shard__", "1_of_2", "", "") or label_replace(sum(a{__query_shard__="2_of_2"}), "__query_shard__", "2_of_2", "", "")) / sum(label_replace(count(a{__query_shard__="1_of_2"}), "__query_shard__", "1_of_2", "", "") or label_replace(count(a{__query_shard__="2_of_2"}), "__query_shard__", "2_of_2", "", ""))"#
+        );
+        assert_eq!(
+            sharded("topk(3, a) by (job)", 2),
+            r#"label_replace(topk by (job) (3, label_replace(topk by (job) (3, a{__query_shard__="1_of_2"}), "__query_shard__", "1_of_2", "", "") or label_replace(topk by (job) (3, a{__query_shard__="2_of_2"}), "__query_shard__", "2_of_2", "", "")), "__query_shard__", "", "", "")"#
+        );
+    }
+
+    #[test]
+    fn test_shard_partially() {
+        let (_, expr) = parse_expr("sum(a) / on () scalar(b)").unwrap();
+        let analysis = analyze(&expr);
+        assert_eq!(analysis.shardable, vec![Path(vec![PathSegment::Lhs])]);
+        assert_eq!(
+            analysis
+                .reasons
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>(),
+            vec!["$.rhs.args[0]: selector is not below a shardable aggregation"]
+        );
+        assert_eq!(shard(&expr, 4).unwrap().reasons, analysis.reasons);
+    }
+
+    #[test]
+    fn test_shard_nested_aggregation() {
+        let (_, expr) = parse_expr("max(sum by (x) (a))").unwrap();
+        let analysis = analyze(&expr);
+        assert_eq!(analysis.shardable, vec![Path(vec![PathSegment::Arg(0)])]);
+        let sharded = shard(&expr, 2).unwrap();
+        assert_eq!(
+            sharded.expr.to_string(),
+            r#"max(sum by (x) (label_replace(sum by (x) (a{__query_shard__="1_of_2"}), "__query_shard__", "1_of_2", "", "") or label_replace(sum by (x) (a{__query_shard__="2_of_2"}), "__query_shard__", "2_of_2", "", "")))"#
+        );
+        assert_eq!(
+            sharded
+                .reasons
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>(),
+            vec!["$.args[0]: nested aggregation sum() groups series across shards"]
+        );
+
+        assert_eq!(
+            reasons("quantile(0.9, sum(a / b) by (x))"),
+            vec![
+                "$: quantile() cannot be merged from partial results",
+                "$.args[1].args[0]: '/' between two vectors matches series across shards",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_not_shardable() {
+        assert_eq!(
+            reasons("sum(a / b)"),
+            vec!["$.args[0]: '/' between two vectors matches series across shards"]
+        );
+        assert_eq!(
+            reasons("max(sum(a) by (job))"),
+            vec!["$.args[0]: nested aggregation sum() groups series across shards"]
+        );
+        assert_eq!(
+            reasons("quantile(0.9, a)"),
+            vec!["$: quantile() cannot be merged from partial results"]
+        );
+        assert_eq!(
+            reasons("sum(histogram_quantile(0.9, a))"),
+            vec!["$.args[0]: histogram_quantile() combines series across shards"]
+        );
+
+        let errors = |q: &str, shards: usize| -> Vec<String> {
+            let (_, expr) = parse_expr(q).unwrap();
+            let reasons = shard(&expr, shards).unwrap_err();
+            reasons.iter().map(|r| r.to_string()).collect()
+        };
+        assert_eq!(
+            errors("rate(a[5m])", 2),
+            vec!["$.args[0]: selector is not below a shardable aggregation"]
+        );
+        assert_eq!(errors("1", 2), vec!["$: query has no aggregation to shard"]);
+        assert_eq!(
+            errors("sum(a)", 1),
+            vec!["$: shard count must be at least 2"]
+        );
+    }
+}
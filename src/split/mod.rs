@@ -0,0 +1,208 @@
+//! Splitting of range queries into step-aligned fragments for results caching.
+//!
+//! All timestamps are milliseconds since the Unix epoch.
+use crate::ast::{AtModifier, Expr, Vector};
+use crate::timerange::{find_min_max_time, EvalRange};
+use crate::visitor::{walk_mut, VisitorMut};
+use std::convert::Infallible;
+use std::time::Duration;
+
+/// One piece of a split range query, evaluated and cached on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    /// The query to run for this fragment, with `@ start()`/`@ end()` pinned to
+    /// the bounds of the original query.
+    pub expr: Expr,
+    pub eval: EvalRange,
+    /// Raw sample window read by the fragment, accounting for offsets, `@` and
+    /// subquery lookbacks. `None` if the query reads no series.
+    pub data_range: Option<(i64, i64)>,
+    /// Identifies the fragment's result independently of how the query was written.
+    /// It includes the fragment's bounds, so a partial fragment at either end of a
+    /// query never shares its key with the complete one.
+    pub cache_key: String,
+}
+
+fn millis(d: Duration) -> i64 {
+    d.as_millis() as i64
+}
+
+/// Aligns `start` and `end` of a range query down to multiples of its step, so that
+/// equal queries issued at different times evaluate at the same timestamps.
+pub fn step_align(eval: &EvalRange) -> EvalRange {
+    let step = millis(eval.step);
+    if step == 0 {
+        return eval.clone();
+    }
+    EvalRange {
+        start: eval.start - eval.start.rem_euclid(step),
+        end: eval.end - eval.end.rem_euclid(step),
+        ..eval.clone()
+    }
+}
+
+/// Splits a range query at multiples of `interval` (e.g. one day), after aligning
+/// it with `step_align`.
+///
+/// Every fragment covers the steps falling into one interval, so fragments of
+/// overlapping queries line up and can be served from cache.
+pub fn split_by_interval(expr: &Expr, eval: &EvalRange, interval: Duration) -> Vec<Fragment> {
+    let eval = &step_align(eval);
+    let expr = pin_at_modifiers(expr, eval);
+    let fingerprint = expr.fingerprint();
+    let step = millis(eval.step);
+    let lookback = millis(eval.lookback_delta);
+    let interval = millis(interval);
+
+    let fragment = |start: i64, end: i64| {
+        let eval = EvalRange {
+            start,
+            end,
+            ..eval.clone()
+        };
+        Fragment {
+            data_range: find_min_max_time(&expr, &eval),
+            cache_key: format!(
+                "{:016x}:{}:{}:{}:{}",
+                fingerprint, step, lookback, start, end
+            ),
+            expr: expr.clone(),
+            eval,
+        }
+    };
+
+    if step <= 0 || interval <= 0 || eval.start >= eval.end {
+        return vec![fragment(eval.start, eval.end)];
+    }
+
+    let mut fragments = Vec::new();
+    let mut start = eval.start;
+    while start <= eval.end {
+        let mut end = next_interval_boundary(start, step, interval);
+        if end + step > eval.end {
+            end = eval.end;
+        }
+        fragments.push(fragment(start, end));
+        start = end + step;
+    }
+    fragments
+}
+
+/// Last step at or after `t` that still falls before the next multiple of `interval`.
+fn next_interval_boundary(t: i64, step: i64, interval: i64) -> i64 {
+    let target = (t.div_euclid(interval) + 1) * interval - 1;
+    target - (target - t).rem_euclid(step)
+}
+
+struct PinAt {
+    start: i64,
+    end: i64,
+}
+
+impl VisitorMut for PinAt {
+    type Err = Infallible;
+
+    fn visit_vector_expr_mut(&mut self, ast: &mut Vector) -> Result<(), Self::Err> {
+        match ast.at {
+            Some(AtModifier::Start) => ast.at = Some(AtModifier::Timestamp(self.start)),
+            Some(AtModifier::End) => ast.at = Some(AtModifier::Timestamp(self.end)),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `@ start()`/`@ end()`, which would resolve differently per fragment,
+/// with the absolute bounds of the original query.
+pub fn pin_at_modifiers(expr: &Expr, eval: &EvalRange) -> Expr {
+    let mut expr = expr.clone();
+    let mut pin = PinAt {
+        start: eval.start,
+        end: eval.end,
+    };
+    let _ = walk_mut(&mut expr, &mut pin);
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    const MIN: i64 = 60 * 1000;
+    const HOUR: i64 = 60 * MIN;
+
+    #[test]
+    fn test_step_align() {
+        let eval = EvalRange::range(10 * MIN + 5, 20 * MIN + 59_000, Duration::from_secs(60));
+        let aligned = step_align(&eval);
+        assert_eq!((aligned.start, aligned.end), (10 * MIN, 20 * MIN));
+    }
+
+    #[test]
+    fn test_split_by_interval() {
+        let (_, expr) = parse_expr("rate(a[5m] offset 1h)").unwrap();
+        let eval = EvalRange::range(30 * MIN, 150 * MIN, Duration::from_secs(60 * 30));
+        let fragments = split_by_interval(&expr, &eval, Duration::from_secs(3600));
+
+        assert_eq!(
+            fragments
+                .iter()
+                .map(|f| (f.eval.start, f.eval.end, f.data_range))
+                .collect::<Vec<_>>(),
+            vec![
+                (30 * MIN, 30 * MIN, Some((-35 * MIN, -30 * MIN))),
+                (HOUR, 90 * MIN, Some((-5 * MIN, 30 * MIN))),
+                (2 * HOUR, 150 * MIN, Some((55 * MIN, 90 * MIN))),
+            ]
+        );
+        assert_eq!(
+            fragments[1].cache_key,
+            format!("{:016x}:1800000:300000:3600000:5400000", expr.fingerprint())
+        );
+    }
+
+    #[test]
+    fn test_partial_fragments_have_own_keys() {
+        let (_, expr) = parse_expr("sum(rate(a[5m]))").unwrap();
+        let day = Duration::from_secs(24 * 3600);
+        let key = |start: i64, end: i64| {
+            let eval = EvalRange::range(start, end, Duration::from_secs(60));
+            let fragments = split_by_interval(&expr, &eval, day);
+            assert_eq!(fragments.len(), 1);
+            fragments[0].cache_key.clone()
+        };
+
+        let full = key(0, 24 * HOUR - MIN);
+        assert_ne!(key(0, 10 * HOUR), full);
+        assert_ne!(key(12 * HOUR, 24 * HOUR - MIN), full);
+        // Unaligned bounds are aligned to the step first.
+        assert_eq!(key(5, 24 * HOUR - MIN + 59_000), full);
+        assert_ne!(key(0, 10 * HOUR), key(MIN, 10 * HOUR));
+    }
+
+    #[test]
+    fn test_cache_key_is_normalised() {
+        let eval = EvalRange::range(0, 2 * HOUR, Duration::from_secs(60));
+        let (_, a) = parse_expr(r#"sum(x{b="2", a="1"}) by (z, y)"#).unwrap();
+        let (_, b) = parse_expr(r#"sum by (y, z) (x{a="1", b="2"})"#).unwrap();
+
+        let a = split_by_interval(&a, &eval, Duration::from_secs(3600));
+        let b = split_by_interval(&b, &eval, Duration::from_secs(3600));
+        assert_eq!(a.len(), 3);
+        assert_eq!(
+            a.iter().map(|f| &f.cache_key).collect::<Vec<_>>(),
+            b.iter().map(|f| &f.cache_key).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pin_at_modifiers() {
+        let (_, expr) = parse_expr("a @ start() - a @ end()").unwrap();
+        let eval = EvalRange::range(0, 2 * HOUR, Duration::from_secs(60));
+        let fragments = split_by_interval(&expr, &eval, Duration::from_secs(3600));
+
+        assert_eq!(fragments[2].expr.to_string(), "a @ 0 - a @ 7200");
+        assert_eq!(fragments[2].data_range, Some((-5 * MIN, 2 * HOUR)));
+    }
+}
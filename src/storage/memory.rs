@@ -0,0 +1,365 @@
+use crate::ast::{LabelMatcher, LabelMatcherOp};
+use crate::matchers::{compile, Matcher};
+use crate::storage::{
+    Querier, Queryable, SampleIter, SelectHints, Series, SeriesSet, StorageError,
+};
+use crate::value::{Labels, Point};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{RwLock, RwLockReadGuard};
+
+#[derive(Debug, Default)]
+struct Head {
+    series: Vec<(Labels, Vec<Point>)>,
+    ids: HashMap<Labels, usize>,
+    /// Ids of the series carrying each label name and value, in ascending order.
+    postings: BTreeMap<String, BTreeMap<String, Vec<usize>>>,
+}
+
+impl Head {
+    fn all(&self) -> Vec<usize> {
+        (0..self.series.len()).collect()
+    }
+
+    /// Ids of series a matcher can only accept if they carry the label, or
+    /// `None` for matchers that also accept series without it.
+    fn postings_for(&self, m: &Matcher) -> Option<Vec<usize>> {
+        if m.matches("") {
+            return None;
+        }
+        let values = match self.postings.get(&m.name) {
+            Some(values) => values,
+            None => return Some(Vec::new()),
+        };
+        if matches!(m.op, LabelMatcherOp::Equal | LabelMatcherOp::None) {
+            return Some(values.get(&m.value).cloned().unwrap_or_default());
+        }
+
+        let mut ids: Vec<usize> = values
+            .iter()
+            .filter(|(v, _)| m.matches(v))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        ids.sort_unstable();
+        Some(ids)
+    }
+
+    /// Ids of series matching all `matchers`: postings of the matchers that
+    /// require their label are intersected, the rest are checked per series.
+    fn select(&self, matchers: &[Matcher]) -> Vec<usize> {
+        let mut ids: Option<Vec<usize>> = None;
+        for m in matchers {
+            if let Some(postings) = self.postings_for(m) {
+                ids = Some(match ids {
+                    Some(ids) => intersect(&ids, &postings),
+                    None => postings,
+                });
+            }
+        }
+
+        let mut ids = ids.unwrap_or_else(|| self.all());
+        ids.retain(|&id| self.series[id].0.matches(matchers));
+        ids
+    }
+}
+
+fn intersect(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            out.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Points of `points` within `[mint, maxt]`.
+fn window(points: &[Point], mint: i64, maxt: i64) -> &[Point] {
+    let start = points.partition_point(|p| p.t < mint);
+    let end = points.partition_point(|p| p.t <= maxt);
+    &points[start..end.max(start)]
+}
+
+/// An in-memory time series database with an inverted index over label pairs.
+///
+/// Samples of a series must be appended in time order. Any number of queriers
+/// can read concurrently; appends wait until open queriers are dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    head: RwLock<Head>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a sample to the series identified by `labels`, creating it if needed.
+    pub fn append(&self, labels: &Labels, t: i64, v: f64) -> Result<(), StorageError> {
+        let mut head = self.head.write().unwrap_or_else(|e| e.into_inner());
+        let head = &mut *head;
+
+        let id = match head.ids.get(labels) {
+            Some(&id) => id,
+            None => {
+                let id = head.series.len();
+                head.series.push((labels.clone(), Vec::new()));
+                head.ids.insert(labels.clone(), id);
+                for l in labels {
+                    head.postings
+                        .entry(l.name.clone())
+                        .or_default()
+                        .entry(l.value.clone())
+                        .or_default()
+                        .push(id);
+                }
+                id
+            }
+        };
+
+        let points = &mut head.series[id].1;
+        match points.last() {
+            Some(last) if t < last.t => Err(StorageError::OutOfOrderSample {
+                labels: labels.to_string(),
+                t,
+            }),
+            Some(last) if t == last.t && v.to_bits() != last.v.to_bits() => {
+                Err(StorageError::DuplicateSample {
+                    labels: labels.to_string(),
+                    t,
+                })
+            }
+            Some(last) if t == last.t => Ok(()),
+            _ => {
+                points.push(Point { t, v });
+                Ok(())
+            }
+        }
+    }
+
+    /// Number of series stored.
+    pub fn len(&self) -> usize {
+        self.read().series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Head> {
+        self.head.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Queryable for MemoryStorage {
+    fn querier<'a>(&'a self, mint: i64, maxt: i64) -> Result<Box<dyn Querier + 'a>, StorageError> {
+        Ok(Box::new(MemoryQuerier {
+            head: self.read(),
+            mint,
+            maxt,
+        }))
+    }
+}
+
+struct MemoryQuerier<'a> {
+    head: RwLockReadGuard<'a, Head>,
+    mint: i64,
+    maxt: i64,
+}
+
+impl MemoryQuerier<'_> {
+    /// Ids of matching series with samples in the querier's window.
+    fn ids(&self, matchers: &[LabelMatcher]) -> Result<Vec<usize>, StorageError> {
+        let matchers = compile(matchers)?;
+        let mut ids = self.head.select(&matchers);
+        ids.retain(|&id| !window(&self.head.series[id].1, self.mint, self.maxt).is_empty());
+        Ok(ids)
+    }
+}
+
+struct SliceSeries<'a> {
+    labels: &'a Labels,
+    points: &'a [Point],
+}
+
+impl Series for SliceSeries<'_> {
+    fn labels(&self) -> &Labels {
+        self.labels
+    }
+
+    fn samples(&self) -> SampleIter<'_> {
+        Box::new(self.points.iter().copied())
+    }
+}
+
+impl Querier for MemoryQuerier<'_> {
+    fn select<'a>(
+        &'a self,
+        sorted: bool,
+        _hints: Option<&SelectHints>,
+        matchers: &[LabelMatcher],
+    ) -> Result<SeriesSet<'a>, StorageError> {
+        let mut ids = self.ids(matchers)?;
+        if sorted {
+            ids.sort_by(|&a, &b| self.head.series[a].0.cmp(&self.head.series[b].0));
+        }
+        Ok(Box::new(ids.into_iter().map(move |id| {
+            let (labels, points) = &self.head.series[id];
+            Box::new(SliceSeries {
+                labels,
+                points: window(points, self.mint, self.maxt),
+            }) as Box<dyn Series>
+        })))
+    }
+
+    fn label_names(&self, matchers: &[LabelMatcher]) -> Result<Vec<String>, StorageError> {
+        let names: BTreeSet<&str> = self
+            .ids(matchers)?
+            .into_iter()
+            .flat_map(|id| self.head.series[id].0.iter().map(|l| l.name.as_str()))
+            .collect();
+        Ok(names.into_iter().map(|n| n.to_owned()).collect())
+    }
+
+    fn label_values(
+        &self,
+        name: &str,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<String>, StorageError> {
+        let values: BTreeSet<&str> = self
+            .ids(matchers)?
+            .into_iter()
+            .filter_map(|id| self.head.series[id].0.get(name))
+            .collect();
+        Ok(values.into_iter().map(|v| v.to_owned()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expr;
+    use crate::matchers::selector_matchers;
+    use crate::parse_expr;
+    use crate::storage::tests::{check_conformance, conformance_series};
+
+    fn storage() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        let series = [
+            Labels::from_pairs(&[("__name__", "up"), ("job", "web"), ("instance", "a")]),
+            Labels::from_pairs(&[("__name__", "up"), ("job", "web"), ("instance", "b")]),
+            Labels::from_pairs(&[("__name__", "up"), ("job", "api")]),
+            Labels::from_pairs(&[("__name__", "down"), ("job", "db")]),
+        ];
+        for t in 0..10 {
+            for (i, labels) in series.iter().enumerate() {
+                storage
+                    .append(labels, t * 10, (t * i as i64) as f64)
+                    .unwrap();
+            }
+        }
+        storage
+    }
+
+    fn select(storage: &MemoryStorage, selector: &str, mint: i64, maxt: i64) -> Vec<String> {
+        let matchers = match parse_expr(selector).unwrap().1 {
+            Expr::VectorExpr(v) => selector_matchers(&v),
+            e => panic!("not a selector: {}", e),
+        };
+        let querier = storage.querier(mint, maxt).unwrap();
+        let out = querier
+            .select(true, None, &matchers)
+            .unwrap()
+            .map(|s| format!("{} {}", s.labels(), s.samples().count()))
+            .collect();
+        out
+    }
+
+    #[test]
+    fn test_select() {
+        let s = storage();
+        assert_eq!(s.len(), 4);
+        assert_eq!(
+            select(&s, r#"up{job="web"}"#, 0, 100),
+            vec![
+                r#"{__name__="up", instance="a", job="web"} 10"#,
+                r#"{__name__="up", instance="b", job="web"} 10"#,
+            ]
+        );
+        assert_eq!(
+            select(&s, r#"{job=~"a.*|d.*"}"#, 15, 40),
+            vec![
+                r#"{__name__="down", job="db"} 3"#,
+                r#"{__name__="up", job="api"} 3"#,
+            ]
+        );
+        assert_eq!(
+            select(&s, r#"up{instance=""}"#, 0, 100),
+            vec![r#"{__name__="up", job="api"} 10"#]
+        );
+        assert_eq!(select(&s, r#"up{instance!="a"}"#, 0, 100).len(), 2);
+        assert!(select(&s, r#"up"#, 100, 200).is_empty());
+        assert!(select(&s, r#"nope{job=~".+"}"#, 0, 100).is_empty());
+    }
+
+    #[test]
+    fn test_conformance() {
+        let s = MemoryStorage::new();
+        for series in conformance_series() {
+            for p in series.points {
+                s.append(&series.labels, p.t, p.v).unwrap();
+            }
+        }
+        check_conformance(&s);
+    }
+
+    #[test]
+    fn test_append_order() {
+        let s = MemoryStorage::new();
+        let labels = Labels::from_pairs(&[("__name__", "x")]);
+        s.append(&labels, 10, 1.0).unwrap();
+        s.append(&labels, 10, 1.0).unwrap();
+        assert!(matches!(
+            s.append(&labels, 10, 2.0),
+            Err(StorageError::DuplicateSample { .. })
+        ));
+        assert!(matches!(
+            s.append(&labels, 5, 1.0),
+            Err(StorageError::OutOfOrderSample { .. })
+        ));
+    }
+
+    #[test]
+    fn test_label_metadata() {
+        let s = storage();
+        let querier = s.querier(0, 100).unwrap();
+        assert_eq!(
+            querier.label_names(&[]).unwrap(),
+            vec!["__name__", "instance", "job"]
+        );
+        assert_eq!(
+            querier.label_values("job", &[]).unwrap(),
+            vec!["api", "db", "web"]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_readers() {
+        let s = std::sync::Arc::new(storage());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let s = s.clone();
+                std::thread::spawn(move || select(&s, "up", 0, 100).len())
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 3);
+        }
+    }
+}
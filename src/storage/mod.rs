@@ -0,0 +1,209 @@
+//! Backends an evaluator reads series from, modelled on Prometheus' `storage.Querier`.
+//!
+//! All timestamps are milliseconds since the Unix epoch.
+use crate::ast::LabelMatcher;
+use crate::matchers::MatcherError;
+use crate::value::{Labels, Point};
+use thiserror::Error;
+
+mod memory;
+pub use memory::*;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum StorageError {
+    #[error(transparent)]
+    Matcher(#[from] MatcherError),
+    #[error("out of order sample for series {labels} at {t}")]
+    OutOfOrderSample { labels: String, t: i64 },
+    #[error("duplicate sample for series {labels} at {t} with a different value")]
+    DuplicateSample { labels: String, t: i64 },
+    #[error("storage: {0}")]
+    Backend(String),
+}
+
+/// What a selection is used for, so a backend may prune or pre-aggregate.
+/// Backends are free to ignore hints and must still return correct data.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SelectHints {
+    pub start: i64,
+    pub end: i64,
+    /// Evaluation step, or 0 for an instant query.
+    pub step: i64,
+    /// Function wrapping the selector, e.g. `rate`.
+    pub func: Option<String>,
+    /// Labels of the enclosing aggregation.
+    pub grouping: Vec<String>,
+    /// Whether `grouping` is a `by` rather than a `without` list.
+    pub by: bool,
+    /// Range of a range selector, or 0 for an instant selector.
+    pub range: i64,
+}
+
+impl SelectHints {
+    pub fn new(start: i64, end: i64) -> Self {
+        Self {
+            start,
+            end,
+            ..Self::default()
+        }
+    }
+}
+
+pub type SampleIter<'a> = Box<dyn Iterator<Item = Point> + 'a>;
+
+/// A stored series: its label set and samples in time order.
+pub trait Series {
+    fn labels(&self) -> &Labels;
+
+    fn samples(&self) -> SampleIter<'_>;
+}
+
+pub type SeriesSet<'a> = Box<dyn Iterator<Item = Box<dyn Series + 'a>> + 'a>;
+
+/// Reads series within the `[mint, maxt]` window it was opened for.
+pub trait Querier {
+    /// Series matching all `matchers`, sorted by labels if `sorted` is set.
+    fn select<'a>(
+        &'a self,
+        sorted: bool,
+        hints: Option<&SelectHints>,
+        matchers: &[LabelMatcher],
+    ) -> Result<SeriesSet<'a>, StorageError>;
+
+    /// Sorted label names of series matching `matchers`.
+    fn label_names(&self, matchers: &[LabelMatcher]) -> Result<Vec<String>, StorageError>;
+
+    /// Sorted values of label `name` on series matching `matchers`.
+    fn label_values(
+        &self,
+        name: &str,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<String>, StorageError>;
+}
+
+pub trait Queryable {
+    fn querier<'a>(&'a self, mint: i64, maxt: i64) -> Result<Box<dyn Querier + 'a>, StorageError>;
+}
+
+/// A series held in memory, for backends that materialise their results.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListSeries {
+    pub labels: Labels,
+    pub points: Vec<Point>,
+}
+
+impl ListSeries {
+    pub fn new(labels: Labels, points: Vec<Point>) -> Self {
+        Self { labels, points }
+    }
+}
+
+impl Series for ListSeries {
+    fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    fn samples(&self) -> SampleIter<'_> {
+        Box::new(self.points.iter().copied())
+    }
+}
+
+/// A conformance suite for `Queryable` implementations.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ast::LabelMatcherOp;
+
+    /// The series `check_conformance` expects the storage under test to hold.
+    pub(crate) fn conformance_series() -> Vec<ListSeries> {
+        let points = |ts: &[i64]| ts.iter().map(|&t| Point { t, v: t as f64 }).collect();
+        vec![
+            ListSeries::new(
+                Labels::from_pairs(&[("__name__", "up"), ("job", "web")]),
+                points(&[10, 20, 30]),
+            ),
+            ListSeries::new(
+                Labels::from_pairs(&[("__name__", "up"), ("job", "api")]),
+                points(&[10, 25, 40]),
+            ),
+            ListSeries::new(
+                Labels::from_pairs(&[("__name__", "down"), ("instance", "a")]),
+                points(&[10]),
+            ),
+        ]
+    }
+
+    fn matcher(name: &str, op: LabelMatcherOp, value: &str) -> LabelMatcher {
+        LabelMatcher {
+            op,
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    /// Checks that `storage`, holding `conformance_series`, selects series and
+    /// label metadata the way `Querier` documents.
+    pub(crate) fn check_conformance(storage: &dyn Queryable) {
+        let querier = storage.querier(15, 35).unwrap();
+        let hints = SelectHints::new(15, 35);
+        let up = [matcher("__name__", LabelMatcherOp::Equal, "up")];
+        let selected: Vec<(String, Vec<Point>)> = querier
+            .select(true, Some(&hints), &up)
+            .unwrap()
+            .map(|s| (s.labels().to_string(), s.samples().collect()))
+            .collect();
+        assert_eq!(
+            selected,
+            vec![
+                (
+                    r#"{__name__="up", job="api"}"#.to_owned(),
+                    vec![Point { t: 25, v: 25.0 }]
+                ),
+                (
+                    r#"{__name__="up", job="web"}"#.to_owned(),
+                    vec![Point { t: 20, v: 20.0 }, Point { t: 30, v: 30.0 }]
+                ),
+            ]
+        );
+
+        // The window is inclusive at both ends, and unsorted selects return the
+        // same series.
+        let querier = storage.querier(10, 30).unwrap();
+        let mut unsorted: Vec<(String, usize)> = querier
+            .select(false, None, &up)
+            .unwrap()
+            .map(|s| (s.labels().to_string(), s.samples().count()))
+            .collect();
+        unsorted.sort();
+        assert_eq!(
+            unsorted,
+            vec![
+                (r#"{__name__="up", job="api"}"#.to_owned(), 2),
+                (r#"{__name__="up", job="web"}"#.to_owned(), 3),
+            ]
+        );
+
+        assert!(querier
+            .select(false, None, &[matcher("job", LabelMatcherOp::Regexp, "(")])
+            .is_err());
+        assert_eq!(
+            querier
+                .select(false, None, &[matcher("job", LabelMatcherOp::Equal, "db")])
+                .unwrap()
+                .count(),
+            0
+        );
+
+        let querier = storage.querier(0, 100).unwrap();
+        assert_eq!(
+            querier.label_names(&[]).unwrap(),
+            vec!["__name__", "instance", "job"]
+        );
+        assert_eq!(querier.label_names(&up).unwrap(), vec!["__name__", "job"]);
+        assert_eq!(
+            querier.label_values("job", &up).unwrap(),
+            vec!["api", "web"]
+        );
+        assert!(querier.label_values("nope", &[]).unwrap().is_empty());
+    }
+}
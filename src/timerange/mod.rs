@@ -0,0 +1,184 @@
+//! Raw sample windows a query reads, in the spirit of Prometheus' `FindMinMaxTime`.
+//!
+//! All timestamps are milliseconds since the Unix epoch.
+use crate::ast::{AtModifier, Expr, Path, Vector};
+use std::time::Duration;
+
+fn millis(d: Duration) -> i64 {
+    d.as_millis() as i64
+}
+
+/// Evaluation parameters of a query. An instant query has `start == end`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EvalRange {
+    pub start: i64,
+    pub end: i64,
+    pub step: Duration,
+    /// How far back an instant selector looks for the latest sample.
+    pub lookback_delta: Duration,
+}
+
+impl EvalRange {
+    pub fn instant(time: i64) -> Self {
+        Self::range(time, time, Duration::default())
+    }
+
+    pub fn range(start: i64, end: i64, step: Duration) -> Self {
+        Self {
+            start,
+            end,
+            step,
+            lookback_delta: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Resolves an `@` modifier against this evaluation.
+    pub fn resolve_at(&self, at: &AtModifier) -> i64 {
+        match at {
+            AtModifier::Timestamp(ts) => *ts,
+            AtModifier::Start => self.start,
+            AtModifier::End => self.end,
+        }
+    }
+}
+
+/// The `[mint, maxt]` window of raw samples needed by one selector.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SelectorRange {
+    pub path: Path,
+    pub mint: i64,
+    pub maxt: i64,
+}
+
+/// Windows of every selector of `expr`, in the order `Expr::selectors` yields them.
+///
+/// Each window accounts for the evaluation range, the ranges of enclosing
+/// subqueries, the selector's own range (or the lookback delta for instant
+/// selectors), `offset` and `@`. A selector pinned with `@` ignores the
+/// evaluation and subquery ranges entirely.
+pub fn selector_ranges(expr: &Expr, eval: &EvalRange) -> Vec<SelectorRange> {
+    let mut out = Vec::new();
+    collect(expr, Path::root(), eval, Duration::default(), &mut out);
+    out
+}
+
+/// Union of all selector windows, or `None` if the query selects no data.
+pub fn find_min_max_time(expr: &Expr, eval: &EvalRange) -> Option<(i64, i64)> {
+    selector_ranges(expr, eval)
+        .into_iter()
+        .fold(None, |acc, r| match acc {
+            None => Some((r.mint, r.maxt)),
+            Some((mint, maxt)) => Some((mint.min(r.mint), maxt.max(r.maxt))),
+        })
+}
+
+/// Window of a single selector nested below subqueries covering `subquery_range`.
+pub fn selector_range(v: &Vector, eval: &EvalRange, subquery_range: Duration) -> (i64, i64) {
+    let (mut start, mut end) = match &v.at {
+        Some(at) => {
+            let ts = eval.resolve_at(at);
+            (ts, ts)
+        }
+        None => (eval.start - millis(subquery_range), eval.end),
+    };
+
+    start -= millis(v.range.unwrap_or(eval.lookback_delta));
+
+    let offset = millis(v.offset.unwrap_or_default());
+    start -= offset;
+    end -= offset;
+
+    (start, end)
+}
+
+fn collect(
+    expr: &Expr,
+    path: Path,
+    eval: &EvalRange,
+    subquery_range: Duration,
+    out: &mut Vec<SelectorRange>,
+) {
+    match expr {
+        Expr::VectorExpr(v) => {
+            let (mint, maxt) = selector_range(v, eval, subquery_range);
+            out.push(SelectorRange { path, mint, maxt });
+        }
+        Expr::SubQueryExpr(s) => {
+            let range = subquery_range + s.range.unwrap_or_default();
+            expr.children()
+                .for_each(|(seg, child)| collect(child, path.child(seg), eval, range, out));
+        }
+        _ => expr
+            .children()
+            .for_each(|(seg, child)| collect(child, path.child(seg), eval, subquery_range, out)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    const MIN: i64 = 60 * 1000;
+
+    fn ranges(input: &str, eval: &EvalRange) -> Vec<(String, i64, i64)> {
+        let (_, expr) = parse_expr(input).unwrap();
+        selector_ranges(&expr, eval)
+            .into_iter()
+            .map(|r| (r.path.to_string(), r.mint, r.maxt))
+            .collect()
+    }
+
+    #[test]
+    fn test_selector_ranges() {
+        let eval = EvalRange::range(100 * MIN, 200 * MIN, Duration::from_secs(60));
+
+        assert_eq!(
+            ranges("rate(a[5m] offset 10m) / b", &eval),
+            vec![
+                ("$.lhs.args[0]".to_owned(), 85 * MIN, 190 * MIN),
+                ("$.rhs".to_owned(), 95 * MIN, 200 * MIN),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subquery_and_at() {
+        let eval = EvalRange::range(100 * MIN, 200 * MIN, Duration::from_secs(60));
+
+        assert_eq!(
+            ranges(
+                "max_over_time(max_over_time(rate(a[5m])[30m:1m])[10m:1m])",
+                &eval
+            ),
+            vec![(
+                "$.args[0].expr.args[0].expr.args[0]".to_owned(),
+                55 * MIN,
+                200 * MIN
+            )]
+        );
+        assert_eq!(
+            ranges("max_over_time(a[5m] @ 3000 offset 1m)[1h:1m]", &eval),
+            vec![("$.expr.args[0]".to_owned(), 44 * MIN, 49 * MIN)]
+        );
+        assert_eq!(
+            ranges("a @ start() + b @ end()", &eval),
+            vec![
+                ("$.lhs".to_owned(), 95 * MIN, 100 * MIN),
+                ("$.rhs".to_owned(), 195 * MIN, 200 * MIN),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_min_max_time() {
+        let (_, expr) = parse_expr("a offset 1h + rate(b[1h])").unwrap();
+        assert_eq!(
+            find_min_max_time(&expr, &EvalRange::instant(200 * MIN)),
+            Some((135 * MIN, 200 * MIN))
+        );
+
+        let (_, expr) = parse_expr("1 + 2").unwrap();
+        assert_eq!(find_min_max_time(&expr, &EvalRange::instant(0)), None);
+    }
+}
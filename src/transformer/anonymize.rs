@@ -0,0 +1,390 @@
+use crate::ast::{BinaryExpr, Expr, FunCall, LabelMatcher, LabelMatcherOp, StringLiteral, Vector};
+use crate::transformer::rename::label_args;
+use crate::transformer::Transformer;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::convert::Infallible;
+
+/// How a string is read, which decides what parts of it must survive verbatim.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Syntax {
+    Plain,
+    Regex,
+    /// `label_replace` replacement with `$1`/`${name}` references.
+    Replacement,
+}
+
+/// Replaces label values, and optionally metric and label names, with salted
+/// pseudonyms so queries can be shared without leaking what they select.
+///
+/// Every run of letters, digits and `_` in a value is replaced by a pseudonym,
+/// the first 64 bits of its HMAC-SHA256 keyed with the salt, so that it can't
+/// be reversed without the salt. Equal words map to equal pseudonyms
+/// across the whole query and across queries anonymised with the same salt.
+/// Punctuation, escapes, and in regular expressions repetition counts, group
+/// flags and POSIX class names are kept, so the result still parses and its
+/// regexes keep their structure. Words inside character classes and group
+/// names are replaced too, along with the references to those groups in
+/// `label_replace` replacements. Labels starting with `__` keep their names.
+#[derive(Debug, Clone, Default)]
+pub struct Anonymizer {
+    pub salt: String,
+    pub metric_names: bool,
+    pub label_names: bool,
+}
+
+impl Anonymizer {
+    pub fn new(salt: &str) -> Self {
+        Self {
+            salt: salt.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn metric_names(mut self, anonymize: bool) -> Self {
+        self.metric_names = anonymize;
+        self
+    }
+
+    pub fn label_names(mut self, anonymize: bool) -> Self {
+        self.label_names = anonymize;
+        self
+    }
+
+    fn pseudonym(&self, prefix: &str, s: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(prefix.as_bytes());
+        mac.update(&[0xff]);
+        mac.update(s.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let mut out = prefix.to_owned();
+        for b in &digest[..8] {
+            out.push_str(&format!("{:02x}", b));
+        }
+        out
+    }
+
+    fn metric(&self, name: &str) -> String {
+        if self.metric_names && !name.is_empty() {
+            self.pseudonym("metric_", name)
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        if self.label_names && !name.is_empty() && !name.starts_with("__") {
+            self.pseudonym("label_", name)
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn labels(&self, labels: &mut [String]) {
+        labels.iter_mut().for_each(|l| *l = self.label(l));
+    }
+
+    fn matcher(&self, m: &LabelMatcher) -> LabelMatcher {
+        let regex = matches!(m.op, LabelMatcherOp::Regexp | LabelMatcherOp::NotRegexp);
+        let value = if m.name == "__name__" {
+            match regex {
+                false => self.metric(&m.value),
+                true if self.metric_names => self.scramble(&m.value, Syntax::Regex),
+                true => m.value.clone(),
+            }
+        } else if regex {
+            self.scramble(&m.value, Syntax::Regex)
+        } else {
+            self.scramble(&m.value, Syntax::Plain)
+        };
+
+        LabelMatcher {
+            op: m.op.clone(),
+            name: self.label(&m.name),
+            value,
+        }
+    }
+
+    /// Replaces the words of `s`, a string in its escaped source form.
+    fn scramble(&self, s: &str, syntax: Syntax) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut out = String::new();
+        let mut i = 0;
+        let mut in_class = false;
+
+        // Copies `chars[i..]` up to and including the first of `ends`.
+        let copy_until = |out: &mut String, i: &mut usize, ends: &[char]| {
+            while *i < chars.len() {
+                let c = chars[*i];
+                out.push(c);
+                *i += 1;
+                if ends.contains(&c) {
+                    break;
+                }
+            }
+        };
+        // Takes the word starting at `chars[*i]`, possibly empty.
+        let take_word = |i: &mut usize| {
+            let start = *i;
+            while *i < chars.len() && is_word(chars[*i]) {
+                *i += 1;
+            }
+            chars[start..*i].iter().collect::<String>()
+        };
+        // Group names and numbers are replaced like words, so references in a
+        // `label_replace` replacement keep pointing at their group.
+        let group = |name: &str| {
+            if name.chars().all(|c| c.is_ascii_digit()) {
+                name.to_owned()
+            } else {
+                self.pseudonym("v", name)
+            }
+        };
+        // Whether `chars[i..]` is a repetition like `{2}`, `{2,}` or `{2,3}`.
+        let is_repetition = |i: usize| {
+            let rest: String = chars[i..].iter().take_while(|&&c| c != '}').collect();
+            let mut bounds = rest[1..].splitn(2, ',');
+            let min = bounds.next().unwrap_or_default();
+            i + rest.chars().count() < chars.len()
+                && !min.is_empty()
+                && min.chars().all(|c| c.is_ascii_digit())
+                && bounds.all(|max| max.chars().all(|c| c.is_ascii_digit()))
+        };
+
+        while i < chars.len() {
+            let c = chars[i];
+            let regex = syntax == Syntax::Regex;
+            if c == '\\' {
+                // A string escape, and for regexes the regex escape it encodes.
+                let len = match chars.get(i + 1) {
+                    Some('\\') if regex => 3,
+                    _ => 2,
+                };
+                let end = (i + len).min(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            } else if regex && !in_class && c == '[' {
+                // Only the brackets, negation and ranges of a class are kept;
+                // a `]` right after the opening bracket is a literal.
+                in_class = true;
+                out.push(c);
+                i += 1;
+                if chars.get(i) == Some(&'^') {
+                    out.push('^');
+                    i += 1;
+                }
+                if chars.get(i) == Some(&']') {
+                    out.push(']');
+                    i += 1;
+                }
+            } else if in_class && c == '[' && chars.get(i + 1) == Some(&':') {
+                copy_until(&mut out, &mut i, &[']']);
+            } else if in_class && c == ']' {
+                in_class = false;
+                out.push(c);
+                i += 1;
+            } else if regex && !in_class && c == '{' && is_repetition(i) {
+                copy_until(&mut out, &mut i, &['}']);
+            } else if regex && !in_class && c == '(' && chars.get(i + 1) == Some(&'?') {
+                let named = match (chars.get(i + 2), chars.get(i + 3)) {
+                    (Some('P'), Some('<')) => 4,
+                    (Some('<'), _) => 3,
+                    _ => 0,
+                };
+                if named > 0 {
+                    out.extend(&chars[i..i + named]);
+                    i += named;
+                    let name = take_word(&mut i);
+                    out.push_str(&group(&name));
+                } else {
+                    // Flags, like `(?i)` or `(?s:`.
+                    copy_until(&mut out, &mut i, &[')', ':']);
+                }
+            } else if syntax == Syntax::Replacement && c == '$' {
+                out.push(c);
+                i += 1;
+                match chars.get(i) {
+                    Some('$') => {
+                        out.push('$');
+                        i += 1;
+                    }
+                    Some('{') => {
+                        out.push('{');
+                        i += 1;
+                        let name = take_word(&mut i);
+                        out.push_str(&group(&name));
+                    }
+                    _ => {
+                        let name = take_word(&mut i);
+                        out.push_str(&group(&name));
+                    }
+                }
+            } else if is_word(c) {
+                let word = take_word(&mut i);
+                out.push_str(&self.pseudonym("v", &word));
+            } else {
+                out.push(c);
+                i += 1;
+            }
+        }
+        out
+    }
+
+    fn string(&self, s: &str, syntax: Syntax) -> Expr {
+        Expr::StringLiteralExpr(Box::new(StringLiteral::new(self.scramble(s, syntax))))
+    }
+}
+
+impl Transformer for Anonymizer {
+    type Err = Infallible;
+
+    fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
+        let mut op = ast.op.clone();
+        if let Some(m) = op.modifier_mut() {
+            self.labels(&mut m.labels);
+            if let Some(group) = m.group.as_mut() {
+                self.labels(&mut group.labels);
+            }
+        }
+
+        Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+            op,
+            lhs: self.transform_expr(&ast.lhs)?,
+            rhs: self.transform_expr(&ast.rhs)?,
+        })))
+    }
+
+    fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
+        let label_args = label_args(ast);
+        let mut args = Vec::with_capacity(ast.args.len());
+        for (i, arg) in ast.args.iter().enumerate() {
+            let arg = match arg {
+                Expr::StringLiteralExpr(s) if label_args.contains(&i) => {
+                    Expr::StringLiteralExpr(Box::new(StringLiteral::new(self.label(&s.value))))
+                }
+                Expr::StringLiteralExpr(s) if ast.name == "label_replace" && i == 2 => {
+                    self.string(&s.value, Syntax::Replacement)
+                }
+                Expr::StringLiteralExpr(s) if ast.name == "label_replace" && i == 4 => {
+                    self.string(&s.value, Syntax::Regex)
+                }
+                _ => self.transform_expr(arg)?,
+            };
+            args.push(arg);
+        }
+
+        let mut aggregation = ast.aggregation.clone();
+        if let Some(a) = aggregation.as_mut() {
+            self.labels(&mut a.labels);
+        }
+
+        Ok(Expr::FunCallExpr(Box::new(FunCall {
+            name: ast.name.clone(),
+            args,
+            aggregation,
+        })))
+    }
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        Ok(Expr::VectorExpr(Box::new(Vector {
+            name: self.metric(&ast.name),
+            label_matchers: ast.label_matchers.iter().map(|m| self.matcher(m)).collect(),
+            ..ast.clone()
+        })))
+    }
+
+    fn transform_string_literal(&mut self, ast: &StringLiteral) -> Result<Expr, Self::Err> {
+        Ok(self.string(&ast.value, Syntax::Plain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+    use crate::transformer::transform;
+    use regex::Regex;
+
+    fn anonymize(a: &mut Anonymizer, input: &str) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        let out = transform(&expr, a).unwrap();
+        let printed = out.to_string();
+        assert_eq!(parse_expr(&printed), Ok(("", out)), "{}", printed);
+        printed
+    }
+
+    #[test]
+    fn test_anonymize_values() {
+        let a = Anonymizer::new("s3cret");
+        let v = |s: &str| a.pseudonym("v", s);
+        let (acme, web, api, lower_a, lower_z) = (v("acme"), v("web"), v("api"), v("a"), v("z"));
+
+        assert_eq!(
+            anonymize(
+                &mut Anonymizer::new("s3cret"),
+                r#"sum by (customer) (rate(http_requests_total{customer="acme", job=~"web|api-.*", path=~"[a-z]+\\.acme{2,3}"}[5m]))"#
+            ),
+            format!(
+                r#"sum by (customer) (rate(http_requests_total{{customer="{acme}", job=~"{web}|{api}-.*", path=~"[{lower_a}-{lower_z}]+\\.{acme}{{2,3}}"}}[5m]))"#,
+            )
+        );
+        // HMAC-SHA256("s3cret", "v" 0xff "acme"), truncated to 64 bits.
+        assert_eq!(acme, "v89bf68fa510f5189");
+        assert_ne!(
+            anonymize(&mut Anonymizer::new("other"), r#"up{customer="acme"}"#),
+            anonymize(&mut Anonymizer::new("s3cret"), r#"up{customer="acme"}"#)
+        );
+    }
+
+    #[test]
+    fn test_anonymize_regex_structure() {
+        let mut a = Anonymizer::new("s3cret");
+        let out = anonymize(
+            &mut a,
+            r#"label_replace(up{tenant=~"[^Aa]cme{2}|[[:alpha:]]+"}, "c", "${customer}-$1$$x", "instance", "(?P<customer>Acme)-(?i:globex)")"#,
+        );
+        for leaked in &["Aa", "cme", "customer", "Acme", "globex"] {
+            assert!(!out.contains(leaked), "{} leaks {}", out, leaked);
+        }
+
+        let v = |s: &str| a.pseudonym("v", s);
+        let strings: Vec<&str> = out.split('"').skip(1).step_by(2).collect();
+        assert_eq!(
+            strings[0],
+            format!("[^{}]{}{{2}}|[[:alpha:]]+", v("Aa"), v("cme"))
+        );
+        assert_eq!(strings[2], format!("${{{}}}-$1$${}", v("customer"), v("x")));
+        assert_eq!(
+            strings[4],
+            format!("(?P<{}>{})-(?i:{})", v("customer"), v("Acme"), v("globex"))
+        );
+        for re in &[strings[0], strings[4]] {
+            assert!(Regex::new(re).is_ok(), "{}", re);
+        }
+    }
+
+    #[test]
+    fn test_anonymize_names() {
+        let mut a = Anonymizer::new("s3cret")
+            .metric_names(true)
+            .label_names(true);
+        let (m, l, src) = (
+            a.pseudonym("metric_", "acme_orders"),
+            a.pseudonym("label_", "tenant"),
+            a.pseudonym("label_", "host"),
+        );
+        let (one, x) = (a.pseudonym("v", "1"), a.pseudonym("v", "x"));
+
+        assert_eq!(
+            anonymize(
+                &mut a,
+                r#"label_replace(acme_orders{__name__="acme_orders"}, "tenant", "$1-x", "host", "(?i)(.*):1")"#
+            ),
+            format!(
+                r#"label_replace({m}{{__name__="{m}"}}, "{l}", "$1-{x}", "{src}", "(?i)(.*):{one}")"#
+            )
+        );
+    }
+}
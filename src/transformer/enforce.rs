@@ -0,0 +1,254 @@
+use crate::ast::{
+    AggregationModifier, AggregationModifierAction, BinaryExpr, BinaryModifierAction, Expr,
+    FunCall, LabelMatcher, LabelMatcherOp, Vector,
+};
+use crate::transformer::Transformer;
+use thiserror::Error;
+
+/// What to do when a selector already carries a matcher on the enforced label.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum EnforceMode {
+    /// Drop the user supplied matchers on the label and inject the enforced one.
+    #[default]
+    Override,
+    /// Fail unless the user supplied matcher is exactly the enforced one.
+    Error,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum EnforceError {
+    #[error("label matcher {name}{op}{value:?} conflicts with enforced label")]
+    ConflictingMatcher {
+        name: String,
+        op: String,
+        value: String,
+    },
+
+    #[error("{func}() must not write to enforced label {label}")]
+    EnforcedLabelTarget { func: String, label: String },
+}
+
+/// Forces a label matcher onto every vector selector of a query, in the spirit of
+/// prom-label-proxy.
+///
+/// Functions that write labels (`label_replace`, `label_join`, `count_values`) are
+/// rejected when they target the enforced label. With `preserve_in_grouping` set, the
+/// label is also added to every `by (…)`/`on (…)` list and removed from every
+/// `without (…)`/`ignoring (…)` list, so each result series stays attributable.
+#[derive(Debug, Clone, Default)]
+pub struct LabelEnforcer {
+    pub name: String,
+    pub value: String,
+    pub mode: EnforceMode,
+    pub preserve_in_grouping: bool,
+}
+
+impl LabelEnforcer {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn matcher(&self) -> LabelMatcher {
+        LabelMatcher {
+            op: LabelMatcherOp::Equal,
+            name: self.name.clone(),
+            value: self.value.clone(),
+        }
+    }
+
+    fn enforce_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<LabelMatcher>, EnforceError> {
+        let enforced = self.matcher();
+        let mut out = Vec::with_capacity(matchers.len() + 1);
+
+        for m in matchers {
+            if m.name != self.name {
+                out.push(m.clone());
+                continue;
+            }
+
+            if self.mode == EnforceMode::Error && *m != enforced {
+                return Err(EnforceError::ConflictingMatcher {
+                    name: m.name.clone(),
+                    op: match m.op {
+                        LabelMatcherOp::Equal => "=",
+                        LabelMatcherOp::NotEqual => "!=",
+                        LabelMatcherOp::Regexp => "=~",
+                        LabelMatcherOp::NotRegexp => "!~",
+                        LabelMatcherOp::None => "",
+                    }
+                    .to_owned(),
+                    value: m.value.clone(),
+                });
+            }
+        }
+
+        out.push(enforced);
+        Ok(out)
+    }
+
+    fn check_label_target(&self, ast: &FunCall) -> Result<(), EnforceError> {
+        let target = match ast.name.as_str() {
+            "label_replace" | "label_join" => ast.args.get(1),
+            "count_values" => ast.args.first(),
+            _ => None,
+        };
+
+        match target {
+            Some(Expr::StringLiteralExpr(s)) if s.value == self.name => {
+                Err(EnforceError::EnforcedLabelTarget {
+                    func: ast.name.clone(),
+                    label: self.name.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn preserve_labels(&self, labels: &mut Vec<String>, keep: bool) {
+        labels.retain(|l| *l != self.name);
+        if keep {
+            labels.push(self.name.clone());
+        }
+    }
+}
+
+impl Transformer for LabelEnforcer {
+    type Err = EnforceError;
+
+    fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
+        let mut op = ast.op.clone();
+        if self.preserve_in_grouping {
+            if let Some(m) = op.modifier_mut() {
+                self.preserve_labels(&mut m.labels, m.action == BinaryModifierAction::On);
+            }
+        }
+
+        Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+            op,
+            lhs: self.transform_expr(&ast.lhs)?,
+            rhs: self.transform_expr(&ast.rhs)?,
+        })))
+    }
+
+    fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
+        self.check_label_target(ast)?;
+
+        let mut aggregation = ast.aggregation.clone();
+        if self.preserve_in_grouping {
+            if let Some(AggregationModifier { action, labels }) = aggregation.as_mut() {
+                let keep = *action == AggregationModifierAction::By;
+                self.preserve_labels(labels, keep);
+            }
+        }
+
+        let args = ast
+            .args
+            .iter()
+            .map(|arg| self.transform_expr(arg))
+            .collect::<Result<Vec<Expr>, Self::Err>>()?;
+
+        Ok(Expr::FunCallExpr(Box::new(FunCall {
+            name: ast.name.clone(),
+            args,
+            aggregation,
+        })))
+    }
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        Ok(Expr::VectorExpr(Box::new(Vector {
+            label_matchers: self.enforce_matchers(&ast.label_matchers)?,
+            ..ast.clone()
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+    use crate::transformer::transform;
+
+    fn enforce(enforcer: &mut LabelEnforcer, input: &str) -> Result<Expr, EnforceError> {
+        let (_, expr) = parse_expr(input).unwrap();
+        transform(&expr, enforcer)
+    }
+
+    #[test]
+    fn test_enforce_nested_selectors() {
+        let mut e = LabelEnforcer::new("tenant", "x");
+        let (_, expected) = parse_expr(
+            r#"max_over_time(rate(a{tenant="x"}[5m])[30m:1m]) / on (job) sum(b{job="j", tenant="x"}) by (job)"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            enforce(
+                &mut e,
+                r#"max_over_time(rate(a{tenant="y"}[5m])[30m:1m]) / on (job) sum(b{job="j", tenant=~".+"}) by (job)"#
+            ),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_enforce_error_mode() {
+        let mut e = LabelEnforcer {
+            mode: EnforceMode::Error,
+            ..LabelEnforcer::new("tenant", "x")
+        };
+
+        assert_eq!(
+            enforce(&mut e, r#"a{tenant!="x"}"#),
+            Err(EnforceError::ConflictingMatcher {
+                name: "tenant".to_owned(),
+                op: "!=".to_owned(),
+                value: "x".to_owned(),
+            })
+        );
+
+        let (_, expected) = parse_expr(r#"-a{tenant="x"}"#).unwrap();
+        assert_eq!(enforce(&mut e, r#"-a{tenant="x"}"#), Ok(expected));
+    }
+
+    #[test]
+    fn test_enforce_label_targets() {
+        let mut e = LabelEnforcer::new("tenant", "x");
+
+        assert_eq!(
+            enforce(&mut e, r#"label_replace(up, "tenant", "y", "job", "(.*)")"#),
+            Err(EnforceError::EnforcedLabelTarget {
+                func: "label_replace".to_owned(),
+                label: "tenant".to_owned(),
+            })
+        );
+        assert!(enforce(&mut e, r#"count_values("tenant", up)"#).is_err());
+        assert!(enforce(&mut e, r#"label_join(up, "dst", ",", "tenant")"#).is_ok());
+    }
+
+    #[test]
+    fn test_enforce_preserve_in_grouping() {
+        let mut e = LabelEnforcer {
+            preserve_in_grouping: true,
+            ..LabelEnforcer::new("tenant", "x")
+        };
+        let (_, expected) = parse_expr(
+            r#"sum(a{tenant="x"}) without (job) / ignoring (job) sum(b{tenant="x"}) by (job, tenant) + on (tenant) c{tenant="x"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            enforce(
+                &mut e,
+                "sum(a) without (job, tenant) / ignoring (tenant, job) sum(b) by (job) + on () c"
+            ),
+            Ok(expected)
+        );
+    }
+}
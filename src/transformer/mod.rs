@@ -1,5 +1,24 @@
 use crate::ast::{BinaryExpr, Expr, FunCall, NumberLiteral, StringLiteral, SubqueryExpr, Vector};
 
+pub use anonymize::*;
+pub use enforce::*;
+pub use recording::*;
+pub use rename::*;
+pub use simplify::*;
+
+mod anonymize;
+mod enforce;
+mod recording;
+mod rename;
+mod simplify;
+
+/// Rebuilds an `Expr` bottom-up.
+///
+/// Every default method transforms the children of a node first and then
+/// reassembles the node from the results, so an implementation only has to
+/// override the methods for the nodes it wants to rewrite: an overridden
+/// `transform_vector_expr` also applies to vectors nested inside binary
+/// expressions, subqueries, negations and function arguments.
 pub trait Transformer {
     type Err;
 
@@ -16,11 +35,25 @@ pub trait Transformer {
     }
 
     fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
-        Ok(Expr::BinaryExpr(Box::new(ast.clone())))
+        Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+            op: ast.op.clone(),
+            lhs: self.transform_expr(&ast.lhs)?,
+            rhs: self.transform_expr(&ast.rhs)?,
+        })))
     }
 
     fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
-        Ok(Expr::FunCallExpr(Box::new(ast.clone())))
+        let args = ast
+            .args
+            .iter()
+            .map(|arg| self.transform_expr(arg))
+            .collect::<Result<Vec<Expr>, Self::Err>>()?;
+
+        Ok(Expr::FunCallExpr(Box::new(FunCall {
+            name: ast.name.clone(),
+            args,
+            aggregation: ast.aggregation.clone(),
+        })))
     }
 
     fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
@@ -36,14 +69,93 @@ pub trait Transformer {
     }
 
     fn transform_subquery_expr(&mut self, ast: &SubqueryExpr) -> Result<Expr, Self::Err> {
-        Ok(Expr::SubQueryExpr(Box::new(ast.clone())))
+        Ok(Expr::SubQueryExpr(Box::new(SubqueryExpr {
+            expr: self.transform_expr(&ast.expr)?,
+            range: ast.range,
+            resolution: ast.resolution,
+        })))
     }
 
     fn transform_negation_expr(&mut self, ast: &Expr) -> Result<Expr, Self::Err> {
-        Ok(Expr::NegationExpr(Box::new(ast.clone())))
+        Ok(Expr::NegationExpr(Box::new(self.transform_expr(ast)?)))
     }
 }
 
 pub fn transform<T: Transformer>(ast: &Expr, t: &mut T) -> Result<Expr, T::Err> {
     t.transform_expr(ast)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{vector_expr, vector_labels, LabelMatcher, LabelMatcherOp};
+    use crate::parse_expr;
+
+    struct AddJob;
+
+    impl Transformer for AddJob {
+        type Err = ();
+
+        fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+            let mut v = ast.clone();
+            v.label_matchers.push(LabelMatcher {
+                op: LabelMatcherOp::Equal,
+                name: "job".to_owned(),
+                value: "api".to_owned(),
+            });
+            Ok(Expr::VectorExpr(Box::new(v)))
+        }
+    }
+
+    #[test]
+    fn test_transform_nested_vectors() {
+        let (_, expr) =
+            parse_expr("max_over_time(rate(a[5m])[30m:1m]) / -sum(b) by (instance)").unwrap();
+        let (_, expected) = parse_expr(
+            "max_over_time(rate(a{job=\"api\"}[5m])[30m:1m]) / -sum(b{job=\"api\"}) by (instance)",
+        )
+        .unwrap();
+
+        assert_eq!(transform(&expr, &mut AddJob), Ok(expected));
+    }
+
+    #[test]
+    fn test_transform_identity() {
+        struct Identity;
+
+        impl Transformer for Identity {
+            type Err = ();
+        }
+
+        let (_, expr) =
+            parse_expr("label_replace(up, \"a\", \"$1\", \"b\", \"(.*)\") > 1").unwrap();
+        assert_eq!(transform(&expr, &mut Identity), Ok(expr));
+    }
+
+    #[test]
+    fn test_transform_replace_node() {
+        struct Replace;
+
+        impl Transformer for Replace {
+            type Err = ();
+
+            fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
+                match ast.name.as_str() {
+                    "irate" => Ok(Expr::FunCallExpr(Box::new(FunCall {
+                        name: "rate".to_owned(),
+                        args: ast.args.clone(),
+                        aggregation: None,
+                    }))),
+                    _ => Ok(Expr::FunCallExpr(Box::new(ast.clone()))),
+                }
+            }
+        }
+
+        let (_, expr) = parse_expr("irate(a[1m])").unwrap();
+        let (_, expected) = parse_expr("rate(a[1m])").unwrap();
+        assert_eq!(transform(&expr, &mut Replace), Ok(expected));
+
+        let v = vector_expr(vector_labels("a", vec![]));
+        assert_eq!(transform(&v, &mut Replace), Ok(v.clone()));
+    }
+}
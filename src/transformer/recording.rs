@@ -0,0 +1,317 @@
+use crate::ast::{
+    AggregationModifierAction, BinaryExpr, BinaryModifierAction, BinaryOp, Expr, FunCall,
+    LabelMatcher, Vector,
+};
+use crate::transformer::{transform, Transformer};
+use std::convert::Infallible;
+
+/// A Prometheus recording rule: `expr` is precomputed and stored as `record`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingRule {
+    pub record: String,
+    pub expr: Expr,
+}
+
+impl RecordingRule {
+    pub fn new(record: &str, expr: Expr) -> Self {
+        Self {
+            record: record.to_owned(),
+            expr,
+        }
+    }
+}
+
+/// Replaces sub-expressions computed by a recording rule with a selector of the
+/// recorded metric.
+///
+/// A sub-expression matches a rule if it has the same structure, ignoring the
+/// order of label matchers and grouping labels. Every selector of the query may
+/// carry the same extra matchers on top of the rule's, which are then moved
+/// onto the recorded metric, provided the labels they filter on survive every
+/// aggregation and vector matching in the rule unchanged. Rules are tried in
+/// order and the outermost match wins.
+///
+/// Only rules whose expression is a function call, an aggregation or a binary
+/// expression are substituted.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSubstituter {
+    pub rules: Vec<RecordingRule>,
+}
+
+impl RuleSubstituter {
+    pub fn new(rules: Vec<RecordingRule>) -> Self {
+        Self { rules }
+    }
+
+    fn substitute(&self, expr: &Expr) -> Option<Expr> {
+        self.rules.iter().find_map(|rule| {
+            let mut extra = None;
+            if !match_rule(&rule.expr, expr, &mut extra) {
+                return None;
+            }
+            let extra = extra.unwrap_or_default();
+            if !extra.iter().all(|m| can_push_down(&rule.expr, &m.name)) {
+                return None;
+            }
+            Some(Expr::VectorExpr(Box::new(Vector {
+                name: rule.record.clone(),
+                label_matchers: extra,
+                ..Vector::default()
+            })))
+        })
+    }
+}
+
+pub fn substitute_rules(expr: &Expr, rules: &[RecordingRule]) -> Expr {
+    match transform(expr, &mut RuleSubstituter::new(rules.to_vec())) {
+        Ok(e) => e,
+        Err(e) => match e {},
+    }
+}
+
+impl Transformer for RuleSubstituter {
+    type Err = Infallible;
+
+    fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
+        if let Some(e) = self.substitute(&Expr::BinaryExpr(Box::new(ast.clone()))) {
+            return Ok(e);
+        }
+        Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+            op: ast.op.clone(),
+            lhs: self.transform_expr(&ast.lhs)?,
+            rhs: self.transform_expr(&ast.rhs)?,
+        })))
+    }
+
+    fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
+        if let Some(e) = self.substitute(&Expr::FunCallExpr(Box::new(ast.clone()))) {
+            return Ok(e);
+        }
+        let args = ast
+            .args
+            .iter()
+            .map(|arg| self.transform_expr(arg))
+            .collect::<Result<Vec<Expr>, Self::Err>>()?;
+        Ok(Expr::FunCallExpr(Box::new(FunCall {
+            name: ast.name.clone(),
+            args,
+            aggregation: ast.aggregation.clone(),
+        })))
+    }
+}
+
+fn sorted<T: Ord + Clone>(items: &[T]) -> Vec<T> {
+    let mut items = items.to_vec();
+    items.sort();
+    items
+}
+
+fn sorted_matchers(matchers: &[LabelMatcher]) -> Vec<LabelMatcher> {
+    let mut matchers = matchers.to_vec();
+    matchers.sort_by(|a, b| (&a.name, &a.op, &a.value).cmp(&(&b.name, &b.op, &b.value)));
+    matchers
+}
+
+fn normalized_op(op: &BinaryOp) -> BinaryOp {
+    let mut op = op.clone();
+    if let Some(m) = op.modifier_mut() {
+        m.labels.sort();
+        if let Some(g) = m.group.as_mut() {
+            g.labels.sort();
+        }
+    }
+    op
+}
+
+/// Whether `query` is `rule` with the same extra matchers on every selector,
+/// which are collected into `extra`.
+fn match_rule(rule: &Expr, query: &Expr, extra: &mut Option<Vec<LabelMatcher>>) -> bool {
+    match (rule, query) {
+        (Expr::VectorExpr(r), Expr::VectorExpr(q)) => {
+            if r.name != q.name || r.range != q.range || r.offset != q.offset || r.at != q.at {
+                return false;
+            }
+            if !r
+                .label_matchers
+                .iter()
+                .all(|m| q.label_matchers.contains(m))
+            {
+                return false;
+            }
+            let rest = sorted_matchers(
+                &q.label_matchers
+                    .iter()
+                    .filter(|m| !r.label_matchers.contains(m))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+            match extra {
+                Some(e) => *e == rest,
+                None => {
+                    *extra = Some(rest);
+                    true
+                }
+            }
+        }
+        (Expr::FunCallExpr(r), Expr::FunCallExpr(q)) => {
+            let same_aggregation = match (&r.aggregation, &q.aggregation) {
+                (None, None) => true,
+                (Some(r), Some(q)) => {
+                    r.action == q.action && sorted(&r.labels) == sorted(&q.labels)
+                }
+                _ => false,
+            };
+            r.name == q.name
+                && same_aggregation
+                && r.args.len() == q.args.len()
+                && r.args
+                    .iter()
+                    .zip(&q.args)
+                    .all(|(r, q)| match_rule(r, q, extra))
+        }
+        (Expr::BinaryExpr(r), Expr::BinaryExpr(q)) => {
+            normalized_op(&r.op) == normalized_op(&q.op)
+                && match_rule(&r.lhs, &q.lhs, extra)
+                && match_rule(&r.rhs, &q.rhs, extra)
+        }
+        (Expr::SubQueryExpr(r), Expr::SubQueryExpr(q)) => {
+            r.range == q.range
+                && r.resolution == q.resolution
+                && match_rule(&r.expr, &q.expr, extra)
+        }
+        (Expr::NegationExpr(r), Expr::NegationExpr(q)) => match_rule(r, q, extra),
+        (Expr::NumberLiteralExpr(_), Expr::NumberLiteralExpr(_))
+        | (Expr::StringLiteralExpr(_), Expr::StringLiteralExpr(_)) => rule == query,
+        _ => false,
+    }
+}
+
+/// Whether filtering the inputs of `expr` on `label` is the same as filtering its
+/// result, i.e. the label reaches the output untouched.
+fn can_push_down(expr: &Expr, label: &str) -> bool {
+    if label == "__name__" {
+        return false;
+    }
+    expr.walk().all(|(_, e)| match e {
+        Expr::FunCallExpr(f) => {
+            let string_arg = |i: usize| {
+                matches!(f.args.get(i), Some(Expr::StringLiteralExpr(s)) if s.value == label)
+            };
+            let keeps_label = match &f.aggregation {
+                Some(a) => match a.action {
+                    AggregationModifierAction::By => a.labels.iter().any(|l| l == label),
+                    AggregationModifierAction::Without => !a.labels.iter().any(|l| l == label),
+                },
+                None => !f.is_aggregation(),
+            };
+            keeps_label
+                && match f.name.as_str() {
+                    "absent" | "absent_over_time" | "scalar" | "vector" => false,
+                    "label_replace" | "label_join" => !string_arg(1),
+                    "count_values" => !string_arg(0),
+                    "histogram_quantile" => label != "le",
+                    _ => true,
+                }
+        }
+        Expr::BinaryExpr(b) => match b.op.modifier() {
+            // Labels copied from the "one" side by `group_left`/`group_right`
+            // are not filtered by matchers on the "many" side.
+            Some(m) if m.group.as_ref().is_some_and(|g| g.labels.iter().any(|l| l == label)) => {
+                false
+            }
+            Some(m) => match m.action {
+                BinaryModifierAction::On => m.labels.iter().any(|l| l == label),
+                BinaryModifierAction::Ignore => !m.labels.iter().any(|l| l == label),
+            },
+            None => true,
+        },
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn rule(record: &str, expr: &str) -> RecordingRule {
+        RecordingRule::new(record, parse_expr(expr).unwrap().1)
+    }
+
+    fn substituted(input: &str, rules: &[RecordingRule]) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        substitute_rules(&expr, rules).to_string()
+    }
+
+    #[test]
+    fn test_substitute_structural_match() {
+        let rules = [rule(
+            "job:http_requests:rate5m",
+            r#"sum by (job, env) (rate(http_requests_total{code="200", env!="dev"}[5m]))"#,
+        )];
+
+        assert_eq!(
+            substituted(
+                r#"sum(rate(http_requests_total{env!="dev", code="200"}[5m])) by (env, job) / 2"#,
+                &rules
+            ),
+            "job:http_requests:rate5m / 2"
+        );
+        assert_eq!(
+            substituted(
+                r#"sum by (job, env) (rate(http_requests_total{code="200", env!="dev"}[1m]))"#,
+                &rules
+            ),
+            r#"sum by (job, env) (rate(http_requests_total{code="200", env!="dev"}[1m]))"#
+        );
+    }
+
+    #[test]
+    fn test_substitute_pushes_down_extra_matchers() {
+        let rules = [rule(
+            "job:errors:ratio",
+            "sum by (job) (rate(errors_total[5m])) / on (job) sum by (job) (rate(requests_total[5m]))",
+        )];
+
+        assert_eq!(
+            substituted(
+                r#"sum by (job) (rate(errors_total{job="api"}[5m])) / on (job) sum by (job) (rate(requests_total{job="api"}[5m]))"#,
+                &rules
+            ),
+            r#"job:errors:ratio{job="api"}"#
+        );
+
+        // `instance` does not survive the aggregation.
+        let input = r#"sum by (job) (rate(errors_total{instance="a"}[5m])) / on (job) sum by (job) (rate(requests_total{instance="a"}[5m]))"#;
+        assert_eq!(
+            substituted(input, &rules),
+            parse_expr(input).unwrap().1.to_string()
+        );
+
+        // Extra matchers must apply to every selector.
+        let input = r#"sum by (job) (rate(errors_total{job="api"}[5m])) / on (job) sum by (job) (rate(requests_total[5m]))"#;
+        assert_eq!(
+            substituted(input, &rules),
+            parse_expr(input).unwrap().1.to_string()
+        );
+    }
+
+    #[test]
+    fn test_can_push_down() {
+        let (_, expr) =
+            parse_expr(r#"histogram_quantile(0.9, sum without (instance) (rate(a_bucket[5m])))"#)
+                .unwrap();
+        assert!(can_push_down(&expr, "job"));
+        assert!(!can_push_down(&expr, "instance"));
+        assert!(!can_push_down(&expr, "le"));
+        assert!(!can_push_down(&expr, "__name__"));
+
+        let (_, expr) = parse_expr(r#"label_replace(a, "job", "$1", "x", "(.*)")"#).unwrap();
+        assert!(!can_push_down(&expr, "job"));
+        assert!(can_push_down(&expr, "x"));
+
+        let (_, expr) = parse_expr("a * ignoring (x) group_left (team) b").unwrap();
+        assert!(can_push_down(&expr, "job"));
+        assert!(!can_push_down(&expr, "team"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::ast::{BinaryExpr, Expr, FunCall, LabelMatcher, LabelMatcherOp, StringLiteral, Vector};
+use crate::transformer::Transformer;
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+/// Rewrites metric and label names, e.g. after an exporter upgrade.
+///
+/// Metric names are renamed in `Vector::name` and in `__name__` equality matchers;
+/// regular expressions on `__name__` are left untouched. Label names are renamed in
+/// label matchers, `by (…)`/`without (…)`, `on (…)`/`ignoring (…)`, `group_left (…)`/
+/// `group_right (…)` and in the label arguments of `label_replace`, `label_join`,
+/// `count_values` and `sort_by_label`.
+#[derive(Debug, Clone, Default)]
+pub struct Renamer {
+    pub metrics: HashMap<String, String>,
+    pub labels: HashMap<String, String>,
+}
+
+impl Renamer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn metric(mut self, from: &str, to: &str) -> Self {
+        self.metrics.insert(from.to_owned(), to.to_owned());
+        self
+    }
+
+    pub fn label(mut self, from: &str, to: &str) -> Self {
+        self.labels.insert(from.to_owned(), to.to_owned());
+        self
+    }
+
+    fn rename_metric(&self, name: &str) -> String {
+        self.metrics
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_owned())
+    }
+
+    fn rename_label(&self, name: &str) -> String {
+        self.labels
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_owned())
+    }
+
+    fn rename_labels(&self, labels: &mut [String]) {
+        labels.iter_mut().for_each(|l| *l = self.rename_label(l));
+    }
+
+    fn rename_matcher(&self, m: &LabelMatcher) -> LabelMatcher {
+        let value = match m.op {
+            LabelMatcherOp::Equal | LabelMatcherOp::NotEqual if m.name == "__name__" => {
+                self.rename_metric(&m.value)
+            }
+            _ => m.value.clone(),
+        };
+
+        LabelMatcher {
+            op: m.op.clone(),
+            name: self.rename_label(&m.name),
+            value,
+        }
+    }
+}
+
+/// Indices of the arguments that name labels for functions writing or reading labels.
+pub(crate) fn label_args(func: &FunCall) -> Vec<usize> {
+    match func.name.as_str() {
+        "label_replace" => vec![1, 3],
+        "label_join" => (3..func.args.len()).chain(Some(1)).collect(),
+        "count_values" => vec![0],
+        "sort_by_label" | "sort_by_label_desc" => (1..func.args.len()).collect(),
+        _ => vec![],
+    }
+}
+
+impl Transformer for Renamer {
+    type Err = Infallible;
+
+    fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
+        let mut op = ast.op.clone();
+        if let Some(m) = op.modifier_mut() {
+            self.rename_labels(&mut m.labels);
+            if let Some(group) = m.group.as_mut() {
+                self.rename_labels(&mut group.labels);
+            }
+        }
+
+        Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+            op,
+            lhs: self.transform_expr(&ast.lhs)?,
+            rhs: self.transform_expr(&ast.rhs)?,
+        })))
+    }
+
+    fn transform_funcall_expr(&mut self, ast: &FunCall) -> Result<Expr, Self::Err> {
+        let label_args = label_args(ast);
+        let mut args = Vec::with_capacity(ast.args.len());
+        for (i, arg) in ast.args.iter().enumerate() {
+            let arg = match arg {
+                Expr::StringLiteralExpr(s) if label_args.contains(&i) => Expr::StringLiteralExpr(
+                    Box::new(StringLiteral::new(self.rename_label(&s.value))),
+                ),
+                _ => self.transform_expr(arg)?,
+            };
+            args.push(arg);
+        }
+
+        let mut aggregation = ast.aggregation.clone();
+        if let Some(a) = aggregation.as_mut() {
+            self.rename_labels(&mut a.labels);
+        }
+
+        Ok(Expr::FunCallExpr(Box::new(FunCall {
+            name: ast.name.clone(),
+            args,
+            aggregation,
+        })))
+    }
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        Ok(Expr::VectorExpr(Box::new(Vector {
+            name: self.rename_metric(&ast.name),
+            label_matchers: ast
+                .label_matchers
+                .iter()
+                .map(|m| self.rename_matcher(m))
+                .collect(),
+            ..ast.clone()
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+    use crate::transformer::transform;
+
+    fn rename(renamer: &mut Renamer, input: &str) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        transform(&expr, renamer).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_rename_metrics() {
+        let mut r = Renamer::new().metric("node_cpu", "node_cpu_seconds_total");
+
+        assert_eq!(
+            rename(
+                &mut r,
+                r#"rate(node_cpu{mode="idle"}[5m]) / ignoring (mode) {__name__="node_cpu"}"#
+            ),
+            r#"rate(node_cpu_seconds_total{mode="idle"}[5m]) / ignoring (mode) {__name__="node_cpu_seconds_total"}"#
+        );
+        assert_eq!(
+            rename(&mut r, r#"{__name__=~"node_cpu"}"#),
+            r#"{__name__=~"node_cpu"}"#
+        );
+    }
+
+    #[test]
+    fn test_rename_labels() {
+        let mut r = Renamer::new().label("instance", "host");
+
+        assert_eq!(
+            rename(
+                &mut r,
+                r#"sum(up{instance="a"}) by (instance, job) * on (instance) group_left (instance) b"#
+            ),
+            r#"sum by (host, job) (up{host="a"}) * on (host) group_left (host) b"#
+        );
+        assert_eq!(
+            rename(
+                &mut r,
+                r#"label_replace(up, "instance", "$1", "instance", "(.*):.*")"#
+            ),
+            r#"label_replace(up, "host", "$1", "host", "(.*):.*")"#
+        );
+        assert_eq!(
+            rename(
+                &mut r,
+                r#"label_join(up, "instance", ",", "job", "instance")"#
+            ),
+            r#"label_join(up, "host", ",", "job", "host")"#
+        );
+        assert_eq!(
+            rename(&mut r, r#"count_values("instance", up)"#),
+            r#"count_values("host", up)"#
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::ast::{BinaryExpr, BinaryOp, Expr, NumberLiteral, Vector};
+use crate::transformer::{transform, Transformer};
+use std::convert::Infallible;
+
+/// Constant folding and algebraic simplification.
+///
+/// * Arithmetic between number literals is folded with IEEE 754 semantics, as
+///   Prometheus does (`1 / 0` is `+Inf`, `%` is the truncated remainder), and
+///   `bool` comparisons between literals fold to `1` or `0`.
+/// * `x * 1`, `1 * x`, `x / 1`, `x - 0`, `x ^ 1` and `-(-x)` collapse to `x` when
+///   `x` carries no metric name, since applying the operator to a vector
+///   would drop `__name__`. `x + 0` is kept as it turns `-0` into `0`.
+/// * `offset 0s` is removed.
+///
+/// The parser does not keep parentheses, so redundant ones disappear once the
+/// tree is printed again.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Simplifier;
+
+pub fn simplify(expr: &Expr) -> Expr {
+    match transform(expr, &mut Simplifier) {
+        Ok(e) => e,
+        Err(e) => match e {},
+    }
+}
+
+/// Functions whose result keeps the metric name of their input.
+const NAME_PRESERVING_FUNCTIONS: [&str; 11] = [
+    "last_over_time",
+    "sort",
+    "sort_desc",
+    "sort_by_label",
+    "sort_by_label_desc",
+    "label_replace",
+    "label_join",
+    "topk",
+    "bottomk",
+    "limitk",
+    "limit_ratio",
+];
+
+/// Whether the result of `expr` is guaranteed to carry no `__name__` label, so that
+/// removing an operator applied to it doesn't change its labels.
+fn drops_name(expr: &Expr) -> bool {
+    match expr {
+        Expr::NumberLiteralExpr(_) | Expr::StringLiteralExpr(_) | Expr::NegationExpr(_) => true,
+        Expr::FunCallExpr(f) => !NAME_PRESERVING_FUNCTIONS.contains(&f.name.as_str()),
+        Expr::BinaryExpr(b) => match b.op {
+            BinaryOp::Add(_)
+            | BinaryOp::Sub(_)
+            | BinaryOp::Mul(_)
+            | BinaryOp::Div(_)
+            | BinaryOp::Mod(_)
+            | BinaryOp::Power(_) => true,
+            BinaryOp::And(_) | BinaryOp::Or(_) | BinaryOp::Unless(_) => false,
+            _ if b.op.is_bool() => true,
+            // A filtering comparison keeps the labels of its vector side(s).
+            _ => match (number(&b.lhs), number(&b.rhs)) {
+                (Some(_), _) => drops_name(&b.rhs),
+                (_, Some(_)) => drops_name(&b.lhs),
+                _ => drops_name(&b.lhs) && drops_name(&b.rhs),
+            },
+        },
+        Expr::VectorExpr(_) | Expr::SubQueryExpr(_) => false,
+    }
+}
+
+fn number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::NumberLiteralExpr(n) => Some(n.value),
+        _ => None,
+    }
+}
+
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn fold(op: &BinaryOp, lhs: f64, rhs: f64) -> Option<f64> {
+    match op {
+        BinaryOp::Add(_) => Some(lhs + rhs),
+        BinaryOp::Sub(_) => Some(lhs - rhs),
+        BinaryOp::Mul(_) => Some(lhs * rhs),
+        BinaryOp::Div(_) => Some(lhs / rhs),
+        BinaryOp::Mod(_) => Some(lhs % rhs),
+        BinaryOp::Power(_) => Some(lhs.powf(rhs)),
+        // Comparisons between scalars are only valid with `bool`.
+        BinaryOp::Equal(true, _) => Some(bool_to_f64(lhs == rhs)),
+        BinaryOp::NotEqual(true, _) => Some(bool_to_f64(lhs != rhs)),
+        BinaryOp::GreaterThan(true, _) => Some(bool_to_f64(lhs > rhs)),
+        BinaryOp::LessThan(true, _) => Some(bool_to_f64(lhs < rhs)),
+        BinaryOp::GreaterEqual(true, _) => Some(bool_to_f64(lhs >= rhs)),
+        BinaryOp::LessEqual(true, _) => Some(bool_to_f64(lhs <= rhs)),
+        _ => None,
+    }
+}
+
+fn is_identity(op: &BinaryOp, lhs: Option<f64>, rhs: Option<f64>) -> Option<bool> {
+    // Returns which side survives: `Some(true)` keeps lhs, `Some(false)` keeps rhs.
+    match op {
+        BinaryOp::Mul(None) if rhs == Some(1.0) => Some(true),
+        BinaryOp::Mul(None) if lhs == Some(1.0) => Some(false),
+        BinaryOp::Div(None) | BinaryOp::Power(None) if rhs == Some(1.0) => Some(true),
+        BinaryOp::Sub(None) if rhs == Some(0.0) => Some(true),
+        _ => None,
+    }
+}
+
+impl Transformer for Simplifier {
+    type Err = Infallible;
+
+    fn transform_binary_expr(&mut self, ast: &BinaryExpr) -> Result<Expr, Self::Err> {
+        let lhs = self.transform_expr(&ast.lhs)?;
+        let rhs = self.transform_expr(&ast.rhs)?;
+
+        if let (Some(l), Some(r)) = (number(&lhs), number(&rhs)) {
+            if let Some(v) = fold(&ast.op, l, r) {
+                return Ok(Expr::NumberLiteralExpr(Box::new(NumberLiteral::new(v))));
+            }
+        }
+
+        match is_identity(&ast.op, number(&lhs), number(&rhs)) {
+            Some(true) if drops_name(&lhs) => Ok(lhs),
+            Some(false) if drops_name(&rhs) => Ok(rhs),
+            _ => Ok(Expr::BinaryExpr(Box::new(BinaryExpr {
+                op: ast.op.clone(),
+                lhs,
+                rhs,
+            }))),
+        }
+    }
+
+    fn transform_vector_expr(&mut self, ast: &Vector) -> Result<Expr, Self::Err> {
+        let mut v = ast.clone();
+        if v.offset.is_some_and(|o| o.as_nanos() == 0) {
+            v.offset = None;
+        }
+        Ok(Expr::VectorExpr(Box::new(v)))
+    }
+
+    fn transform_negation_expr(&mut self, ast: &Expr) -> Result<Expr, Self::Err> {
+        match self.transform_expr(ast)? {
+            Expr::NumberLiteralExpr(n) => Ok(Expr::NumberLiteralExpr(Box::new(
+                NumberLiteral::new(-n.value),
+            ))),
+            Expr::NegationExpr(inner) if drops_name(&inner) => Ok(*inner),
+            e => Ok(Expr::NegationExpr(Box::new(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn simplified(input: &str) -> String {
+        let (_, expr) = parse_expr(input).unwrap();
+        simplify(&expr).to_string()
+    }
+
+    #[test]
+    fn test_fold_literals() {
+        assert_eq!(simplified("(1 + 2) * y"), "3 * y");
+        assert_eq!(simplified("2 ^ 3 ^ 2 - 12"), "500");
+        assert_eq!(simplified("1 / 0"), "+Inf");
+        assert_eq!(simplified("-7 % 3"), "-1");
+        assert_eq!(simplified("-(2 * 3)"), "-6");
+        assert_eq!(simplified("1 > bool 2"), "0");
+        assert_eq!(simplified("(1 + 1) == bool 2"), "1");
+        assert_eq!(simplified("1 > 2"), "1 > 2");
+    }
+
+    #[test]
+    fn test_remove_identities() {
+        assert_eq!(simplified("sum(x) * 1"), "sum(x)");
+        assert_eq!(simplified("1 * rate(x[5m]) / 1"), "rate(x[5m])");
+        assert_eq!(simplified("-(-sum(x))"), "sum(x)");
+        assert_eq!(simplified("sum(x) + 0"), "sum(x) + 0");
+
+        // The metric name would change, so the operation must stay.
+        assert_eq!(simplified("x * 1"), "x * 1");
+        assert_eq!(simplified("-(-x)"), "-(-x)");
+        assert_eq!(simplified("sum(x) * on (a) 1"), "sum(x) * on (a) 1");
+        assert_eq!(simplified("(1 < x) * 1"), "(1 < x) * 1");
+        assert_eq!(simplified("(x > sum(y)) * 1"), "(x > sum(y)) * 1");
+        assert_eq!(simplified("(1 < sum(x)) * 1"), "1 < sum(x)");
+        assert_eq!(
+            simplified("(sum(x) > rate(y[1m])) * 1"),
+            "sum(x) > rate(y[1m])"
+        );
+    }
+
+    #[test]
+    fn test_remove_zero_offset() {
+        assert_eq!(simplified("rate(x[5m] offset 0s) * (2 - 1)"), "rate(x[5m])");
+        assert_eq!(simplified("x offset 1m"), "x offset 1m");
+    }
+}
@@ -0,0 +1,377 @@
+//! Query results, mirroring the types `ast::ValueType` names.
+//!
+//! Timestamps are milliseconds since the Unix epoch.
+use crate::ast::{FnvHasher, ValueType};
+use crate::matchers::Matcher;
+use std::fmt;
+use std::hash::Hasher;
+use std::iter::FromIterator;
+
+pub const METRIC_NAME: &str = "__name__";
+
+/// Bit pattern of the NaN Prometheus writes to mark a series as stale.
+pub const STALE_NAN_BITS: u64 = 0x7ff0_0000_0000_0002;
+
+/// Whether `v` is a staleness marker rather than an ordinary NaN.
+pub fn is_stale_nan(v: f64) -> bool {
+    v.to_bits() == STALE_NAN_BITS
+}
+
+/// Whether `name` is a valid label name, `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Separates names and values when hashing, as it cannot occur in valid UTF-8.
+const SEP: u8 = 0xff;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Label {
+    pub name: String,
+    pub value: String,
+}
+
+impl Label {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// A label set, sorted by name with unique names and no empty values.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Labels(Vec<Label>);
+
+impl Labels {
+    /// Sorts `labels`, keeping the last value of a repeated name and dropping
+    /// empty values, which Prometheus treats as absent labels.
+    pub fn new(mut labels: Vec<Label>) -> Self {
+        labels.reverse();
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        labels.dedup_by(|a, b| a.name == b.name);
+        labels.retain(|l| !l.value.is_empty());
+        Self(labels)
+    }
+
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Self {
+        Self::new(pairs.iter().map(|(n, v)| Label::new(n, v)).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Label> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .binary_search_by(|l| l.name.as_str().cmp(name))
+            .ok()
+            .map(|i| self.0[i].value.as_str())
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `value`, or removes it if `value` is empty.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.0.binary_search_by(|l| l.name.as_str().cmp(name)) {
+            Ok(i) if value.is_empty() => {
+                self.0.remove(i);
+            }
+            Ok(i) => self.0[i].value = value.to_owned(),
+            Err(_) if value.is_empty() => {}
+            Err(i) => self.0.insert(i, Label::new(name, value)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.set(name, "");
+    }
+
+    pub fn metric_name(&self) -> Option<&str> {
+        self.get(METRIC_NAME)
+    }
+
+    pub fn without_metric_name(&self) -> Labels {
+        self.drop_labels(&[METRIC_NAME])
+    }
+
+    /// Only the labels named in `names`, as for `by (…)` and `on (…)`.
+    pub fn keep_labels<S: AsRef<str>>(&self, names: &[S]) -> Labels {
+        Labels(
+            self.iter()
+                .filter(|l| names.iter().any(|n| n.as_ref() == l.name))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// All but the labels named in `names`.
+    pub fn drop_labels<S: AsRef<str>>(&self, names: &[S]) -> Labels {
+        Labels(
+            self.iter()
+                .filter(|l| !names.iter().any(|n| n.as_ref() == l.name))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Whether the label set satisfies all `matchers`.
+    pub fn matches(&self, matchers: &[Matcher]) -> bool {
+        matchers
+            .iter()
+            .all(|m| m.matches(self.get(&m.name).unwrap_or("")))
+    }
+
+    fn hash_filtered<F: Fn(&Label) -> bool>(&self, include: F) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for l in self.iter().filter(|l| include(l)) {
+            hasher.write(l.name.as_bytes());
+            hasher.write_u8(SEP);
+            hasher.write(l.value.as_bytes());
+            hasher.write_u8(SEP);
+        }
+        hasher.finish()
+    }
+
+    /// Stable hash of the whole label set.
+    pub fn fingerprint(&self) -> u64 {
+        self.hash_filtered(|_| true)
+    }
+
+    /// Hash of only the labels named in `names`, for grouping with `by (…)`
+    /// and matching with `on (…)`.
+    pub fn hash_for_labels<S: AsRef<str>>(&self, names: &[S]) -> u64 {
+        self.hash_filtered(|l| names.iter().any(|n| n.as_ref() == l.name))
+    }
+
+    /// Hash of all labels except `names` and the metric name, for grouping with
+    /// `without (…)` and matching with `ignoring (…)`.
+    pub fn hash_without_labels<S: AsRef<str>>(&self, names: &[S]) -> u64 {
+        self.hash_filtered(|l| l.name != METRIC_NAME && !names.iter().any(|n| n.as_ref() == l.name))
+    }
+}
+
+impl FromIterator<Label> for Labels {
+    fn from_iter<I: IntoIterator<Item = Label>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for &'a Labels {
+    type Item = &'a Label;
+    type IntoIter = std::slice::Iter<'a, Label>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels: Vec<String> = self
+            .iter()
+            .map(|l| format!("{}={:?}", l.name, l.value))
+            .collect();
+        write!(f, "{{{}}}", labels.join(", "))
+    }
+}
+
+/// A single value of a series.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Point {
+    pub t: i64,
+    pub v: f64,
+}
+
+/// One element of an instant vector.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sample {
+    pub metric: Labels,
+    pub t: i64,
+    pub v: f64,
+}
+
+/// One element of a range vector: a series with its points in time order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Series {
+    pub metric: Labels,
+    pub points: Vec<Point>,
+}
+
+pub type Vector = Vec<Sample>;
+pub type Matrix = Vec<Series>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Scalar {
+    pub t: i64,
+    pub v: f64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StringValue {
+    pub t: i64,
+    pub v: String,
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(Scalar),
+    Vector(Vector),
+    Matrix(Matrix),
+    String(StringValue),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Scalar(_) => ValueType::Scalar,
+            Value::Vector(_) => ValueType::Vector,
+            Value::Matrix(_) => ValueType::Matrix,
+            Value::String(_) => ValueType::String,
+        }
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} @{}", self.v, self.t)
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} => {} @{}", self.metric, self.v, self.t)
+    }
+}
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self.points.iter().map(|p| p.to_string()).collect();
+        write!(f, "{} =>\n{}", self.metric, points.join("\n"))
+    }
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "scalar: {} @{}", self.v, self.t)
+    }
+}
+
+impl fmt::Display for StringValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "string: {} @{}", self.v, self.t)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Scalar(s) => write!(f, "{}", s),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Vector(v) => {
+                let samples: Vec<String> = v.iter().map(|s| s.to_string()).collect();
+                write!(f, "{}", samples.join("\n"))
+            }
+            Value::Matrix(m) => {
+                let series: Vec<String> = m.iter().map(|s| s.to_string()).collect();
+                write!(f, "{}", series.join("\n"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::LabelMatcher;
+    use crate::matchers::compile;
+
+    #[test]
+    fn test_labels_are_sorted() {
+        let mut labels = Labels::from_pairs(&[
+            ("job", "api"),
+            (METRIC_NAME, "up"),
+            ("instance", "a"),
+            ("job", "web"),
+            ("empty", ""),
+        ]);
+        assert_eq!(
+            labels.to_string(),
+            r#"{__name__="up", instance="a", job="web"}"#
+        );
+        assert_eq!(labels.metric_name(), Some("up"));
+        assert_eq!(labels.get("empty"), None);
+
+        labels.set("env", "prod");
+        labels.remove("instance");
+        assert_eq!(
+            labels.without_metric_name().to_string(),
+            r#"{env="prod", job="web"}"#
+        );
+    }
+
+    #[test]
+    fn test_label_hashes() {
+        let a = Labels::from_pairs(&[(METRIC_NAME, "x"), ("job", "api"), ("instance", "a")]);
+        let b = Labels::from_pairs(&[(METRIC_NAME, "y"), ("job", "api"), ("instance", "b")]);
+
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.hash_for_labels(&["job"]), b.hash_for_labels(&["job"]));
+        assert_ne!(
+            a.hash_for_labels(&["job", "instance"]),
+            b.hash_for_labels(&["job", "instance"])
+        );
+        assert_eq!(
+            a.hash_without_labels(&["instance"]),
+            b.hash_without_labels(&["instance"])
+        );
+        assert_eq!(
+            a.hash_without_labels(&["instance"]),
+            Labels::from_pairs(&[("job", "api")]).fingerprint()
+        );
+        // Label boundaries are part of the hash.
+        assert_ne!(
+            Labels::from_pairs(&[("a", "bc")]).fingerprint(),
+            Labels::from_pairs(&[("ab", "c")]).fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_labels_match() {
+        let labels = Labels::from_pairs(&[(METRIC_NAME, "up"), ("job", "api")]);
+        let matchers = |ms: &[(&str, crate::ast::LabelMatcherOp, &str)]| {
+            compile(
+                &ms.iter()
+                    .map(|(name, op, value)| LabelMatcher {
+                        op: op.clone(),
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap()
+        };
+        use crate::ast::LabelMatcherOp::*;
+
+        assert!(labels.matches(&matchers(&[
+            (METRIC_NAME, Equal, "up"),
+            ("job", Regexp, "a.*")
+        ])));
+        assert!(labels.matches(&matchers(&[("env", Equal, "")])));
+        assert!(!labels.matches(&matchers(&[("job", NotEqual, "api")])));
+    }
+}
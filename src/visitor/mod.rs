@@ -55,6 +55,65 @@ pub fn visit<V: Visitor>(ast: &Expr, v: &mut V) -> Result<(), V::Err> {
     v.visit_expr(ast)
 }
 
+/// In-place counterpart of `Visitor`.
+///
+/// Unlike `Visitor`, the default methods walk into every child, so overriding a leaf method such as
+/// `visit_vector_expr_mut` is enough to rewrite all matching nodes of a tree without
+/// cloning it the way a `Transformer` does.
+pub trait VisitorMut {
+    type Err;
+
+    fn visit_expr_mut(&mut self, ast: &mut Expr) -> Result<(), Self::Err> {
+        match ast {
+            Expr::BinaryExpr(e) => self.visit_binary_expr_mut(e),
+            Expr::FunCallExpr(e) => self.visit_funcall_expr_mut(e),
+            Expr::VectorExpr(e) => self.visit_vector_expr_mut(e),
+            Expr::NumberLiteralExpr(e) => self.visit_number_literal_mut(e),
+            Expr::StringLiteralExpr(e) => self.visit_string_literal_mut(e),
+            Expr::SubQueryExpr(e) => self.visit_subquery_expr_mut(e),
+            Expr::NegationExpr(e) => self.visit_negation_expr_mut(e),
+        }
+    }
+
+    fn visit_binary_expr_mut(&mut self, ast: &mut BinaryExpr) -> Result<(), Self::Err> {
+        self.visit_expr_mut(&mut ast.lhs)?;
+        self.visit_expr_mut(&mut ast.rhs)
+    }
+
+    fn visit_funcall_expr_mut(&mut self, ast: &mut FunCall) -> Result<(), Self::Err> {
+        ast.args
+            .iter_mut()
+            .try_for_each(|arg| self.visit_expr_mut(arg))
+    }
+
+    fn visit_vector_expr_mut(&mut self, ast: &mut Vector) -> Result<(), Self::Err> {
+        let _ = ast;
+        Ok(())
+    }
+
+    fn visit_number_literal_mut(&mut self, ast: &mut NumberLiteral) -> Result<(), Self::Err> {
+        let _ = ast;
+        Ok(())
+    }
+
+    fn visit_string_literal_mut(&mut self, ast: &mut StringLiteral) -> Result<(), Self::Err> {
+        let _ = ast;
+        Ok(())
+    }
+
+    fn visit_subquery_expr_mut(&mut self, ast: &mut SubqueryExpr) -> Result<(), Self::Err> {
+        self.visit_expr_mut(&mut ast.expr)
+    }
+
+    fn visit_negation_expr_mut(&mut self, ast: &mut Expr) -> Result<(), Self::Err> {
+        self.visit_expr_mut(ast)
+    }
+}
+
+pub fn walk_mut<V: VisitorMut>(ast: &mut Expr, v: &mut V) -> Result<(), V::Err> {
+    v.visit_expr_mut(ast)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,4 +140,55 @@ mod tests {
         visit(&expr, &mut v).unwrap();
         assert_eq!(v.visit_vector, 1);
     }
+
+    #[test]
+    fn test_visit_nested() {
+        struct Names(Vec<String>);
+
+        impl Visitor for Names {
+            type Err = ();
+
+            fn visit_binary_expr(&mut self, ast: &BinaryExpr) -> Result<(), Self::Err> {
+                self.visit_expr(&ast.lhs)?;
+                self.visit_expr(&ast.rhs)
+            }
+
+            fn visit_vector_expr(&mut self, ast: &Vector) -> Result<(), Self::Err> {
+                self.0.push(ast.name.clone());
+                Ok(())
+            }
+
+            fn visit_subquery_expr(&mut self, ast: &SubqueryExpr) -> Result<(), Self::Err> {
+                self.visit_expr(&ast.expr)
+            }
+
+            fn visit_negation_expr(&mut self, ast: &Expr) -> Result<(), Self::Err> {
+                self.visit_expr(ast)
+            }
+        }
+
+        let (_, expr) = parse_expr("-a + max_over_time(rate(b[1m])[10m:1m]) * c").unwrap();
+        let mut v = Names(vec![]);
+        visit(&expr, &mut v).unwrap();
+        assert_eq!(v.0, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_walk_mut() {
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            type Err = ();
+
+            fn visit_vector_expr_mut(&mut self, ast: &mut Vector) -> Result<(), Self::Err> {
+                ast.name = format!("{}_total", ast.name);
+                Ok(())
+            }
+        }
+
+        let (_, mut expr) = parse_expr("sum(rate(a[5m])) by (job) / -b").unwrap();
+        let (_, expected) = parse_expr("sum(rate(a_total[5m])) by (job) / -b_total").unwrap();
+        walk_mut(&mut expr, &mut Rename).unwrap();
+        assert_eq!(expr, expected);
+    }
 }